@@ -0,0 +1,116 @@
+//! Pluggable transport used to run Juju hook tools.
+//!
+//! Every wrapper function in this crate (`config_get`, `relation_set`, `status_set`, ...) hands
+//! the name of the hook tool and its arguments to a `HookToolBackend`.  By default that is the
+//! `ProcessBackend` which runs the tool as a child process.  Charms and tests can install their
+//! own backend with `set_backend` to record, mock or otherwise redirect hook tool calls.
+//!
+//! The installed backend is scoped to the current thread.
+
+use std::cell::RefCell;
+use std::env;
+use std::process::Command;
+use std::rc::Rc;
+
+use super::JujuError;
+
+/// The captured result of running a hook tool
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HookToolOutput {
+    /// The exit code of the tool.  None if the tool was terminated by a signal
+    pub exit_code: Option<i32>,
+    /// Everything the tool wrote to stdout
+    pub stdout: Vec<u8>,
+    /// Everything the tool wrote to stderr
+    pub stderr: Vec<u8>,
+}
+
+impl HookToolOutput {
+    /// Build a successful output with the given stdout
+    pub fn from_stdout<T: Into<Vec<u8>>>(stdout: T) -> HookToolOutput {
+        HookToolOutput {
+            exit_code: Some(0),
+            stdout: stdout.into(),
+            stderr: Vec::new(),
+        }
+    }
+
+    /// Build a failed output with the given exit code and stderr
+    pub fn from_failure<T: Into<Vec<u8>>>(exit_code: i32, stderr: T) -> HookToolOutput {
+        HookToolOutput {
+            exit_code: Some(exit_code),
+            stdout: Vec::new(),
+            stderr: stderr.into(),
+        }
+    }
+
+    /// Returns true if the tool exited with a zero exit code
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Something that knows how to invoke Juju hook tools such as `config-get` or `relation-set`.
+pub trait HookToolBackend {
+    /// Run the named tool with the given arguments and return what it produced.
+    /// # Failures
+    /// Should only return an error if the tool could not be run at all.  A tool that ran and
+    /// exited with a non-zero exit code is reported through `HookToolOutput::exit_code`.
+    fn invoke(&self, tool: &str, args: &[String]) -> Result<HookToolOutput, JujuError>;
+
+    /// Look up one of the environment variables Juju sets for a hook, ie: `JUJU_UNIT_NAME`.
+    /// Defaults to the process environment.
+    fn var(&self, name: &str) -> Result<String, env::VarError> {
+        env::var(name)
+    }
+}
+
+/// The default backend.  Runs each hook tool as a child process found on `$PATH`.
+#[derive(Debug, Default)]
+pub struct ProcessBackend;
+
+impl HookToolBackend for ProcessBackend {
+    fn invoke(&self, tool: &str, args: &[String]) -> Result<HookToolOutput, JujuError> {
+        let output = Command::new(tool).args(args).output()?;
+        Ok(HookToolOutput {
+            exit_code: output.status.code(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+}
+
+thread_local! {
+    static BACKEND: RefCell<Option<Rc<dyn HookToolBackend>>> = RefCell::new(None);
+}
+
+/// Install a backend for all hook tool calls made from the current thread.
+/// Returns the previously installed backend, if there was one, so that it can be restored later.
+pub fn set_backend(backend: Rc<dyn HookToolBackend>) -> Option<Rc<dyn HookToolBackend>> {
+    BACKEND.with(|b| b.borrow_mut().replace(backend))
+}
+
+/// Remove any installed backend so that the `ProcessBackend` is used again.
+/// Returns the backend that was removed, if there was one.
+pub fn reset_backend() -> Option<Rc<dyn HookToolBackend>> {
+    BACKEND.with(|b| b.borrow_mut().take())
+}
+
+/// Run a hook tool through the backend installed for the current thread
+pub fn invoke(tool: &str, args: &[String]) -> Result<HookToolOutput, JujuError> {
+    // Clone the handle out so a backend is free to call set_backend itself
+    let backend = BACKEND.with(|b| b.borrow().clone());
+    match backend {
+        Some(backend) => backend.invoke(tool, args),
+        None => ProcessBackend.invoke(tool, args),
+    }
+}
+
+/// Look up a hook environment variable through the backend installed for the current thread
+pub fn var(name: &str) -> Result<String, env::VarError> {
+    let backend = BACKEND.with(|b| b.borrow().clone());
+    match backend {
+        Some(backend) => backend.var(name),
+        None => env::var(name),
+    }
+}
@@ -0,0 +1,455 @@
+//! The charm's config values and config.yaml schema.
+//!
+//! `Config` snapshots the current config values and tracks what changed since the last
+//! successful hook.  `ConfigSchema` describes every option the charm declares and can check the
+//! live values returned by `config_get_all` against it so a hook can set a Blocked status with a
+//! useful message instead of failing halfway through.
+//! # Examples
+//! ```
+//! extern crate juju;
+//! use juju::config::ConfigSchema;
+//!
+//! let schema: ConfigSchema = "
+//! options:
+//!   port:
+//!     type: int
+//!     default: 80
+//!     description: Port to listen on
+//!   admin_password:
+//!     type: string
+//!     description: Password for the admin user
+//! ".parse().unwrap();
+//!
+//! let live = juju::testing::Harness::new("web/0");
+//! live.set_config("port", "eighty");
+//! let values = juju::config_get_all().unwrap();
+//! for violation in schema.validate(&values) {
+//!     println!("{}", violation);
+//! }
+//! assert_eq!(schema.unset_options(&values), vec!["admin_password".to_string()]);
+//! ```
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde_json;
+use yaml_rust::{Yaml, YamlLoader};
+
+use super::{config_get_all, log, JujuError, LogLevel};
+
+/// The file under `$CHARM_DIR` that holds the config values seen by the last successful hook
+const PERSISTENT_CONFIG: &str = ".juju-persistent-config";
+
+thread_local! {
+    /// The snapshots loaded during the current hook, keyed by path.  Written by `end_hook`
+    static PENDING: RefCell<BTreeMap<PathBuf, HashMap<String, String>>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Called when a hook starts.  Forgets snapshots loaded before it
+pub fn begin_hook() {
+    PENDING.with(|p| p.borrow_mut().clear());
+}
+
+/// Called when a hook finishes.  Commits the snapshot of every Config loaded during the hook
+/// if it succeeded, and discards them otherwise.
+pub fn end_hook(success: bool) -> Result<(), JujuError> {
+    let pending = PENDING.with(|p| std::mem::take(&mut *p.borrow_mut()));
+    if success {
+        for (path, values) in pending {
+            write_snapshot(&path, &values)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_snapshot(path: &Path, values: &HashMap<String, String>) -> Result<(), JujuError> {
+    let serialized = serde_json::to_string(values)?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(path)?;
+    file.write_all(serialized.as_bytes())?;
+    log(format!("{} saved.  Wrote {} bytes", path.display(), serialized.len()),
+        Some(LogLevel::Debug));
+    Ok(())
+}
+
+/// A snapshot of the charm's config values, with some extra features:
+/// - See which values have changed since the previous successful hook.
+/// - For values that have changed, see what the previous value was.
+/// - Fall back to the defaults in config.yaml for options without a value.
+///
+/// The previous snapshot is only replaced once the hook has succeeded.  Hooks run through
+/// `HookRegistry` or `process_hooks` commit every Config loaded during the hook when it
+/// succeeds; elsewhere call `commit` yourself.  A failed hook leaves the snapshot alone so the
+/// next hook sees the same changes again.
+/// # Examples
+/// ```
+/// extern crate juju;
+///
+/// let harness = juju::testing::Harness::new("web/0");
+/// harness.set_config("port", 8080);
+///
+/// let snapshot = std::env::temp_dir().join("juju-config-example");
+/// let config = juju::Config::load_from(snapshot).unwrap();
+/// if config.changed("port") {
+///     // Reconfigure and restart the service
+/// }
+/// // Outside a dispatched hook, save the snapshot explicitly
+/// config.commit().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Config {
+    current: HashMap<String, String>,
+    /// None if no hook has committed a snapshot yet
+    previous: Option<HashMap<String, String>>,
+    path: PathBuf,
+    schema: Option<ConfigSchema>,
+}
+
+/// The differences between the previous and current config snapshots
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigDiff {
+    /// Options that have a value now but had none before
+    pub added: HashMap<String, String>,
+    /// Options that had a value before but have none now
+    pub removed: HashMap<String, String>,
+    /// Options whose value changed, as (previous, current) tuples
+    pub modified: HashMap<String, (String, String)>,
+}
+
+impl ConfigDiff {
+    /// Returns true if nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl Config {
+    /// Snapshot the current config values and load the snapshot committed by the previous
+    /// successful hook from `$CHARM_DIR/.juju-persistent-config`
+    /// # Failures
+    /// Returns a JujuError if CHARM_DIR is not set, config-get fails or the previous snapshot
+    /// cannot be read
+    pub fn new() -> Result<Self, JujuError> {
+        let charm_dir = env::var("CHARM_DIR")?;
+        Config::load_from(PathBuf::from(charm_dir).join(PERSISTENT_CONFIG))
+    }
+
+    /// Like `new` but keeps the previous snapshot at the given path
+    pub fn load_from(path: PathBuf) -> Result<Self, JujuError> {
+        let previous = if path.exists() {
+            let mut s = String::new();
+            File::open(&path)?.read_to_string(&mut s)?;
+            Some(serde_json::from_str(&s)?)
+        } else {
+            None
+        };
+        let current = config_get_all()?;
+        PENDING.with(|p| p.borrow_mut().insert(path.clone(), current.clone()));
+        Ok(Config {
+            current,
+            previous,
+            path,
+            schema: None,
+        })
+    }
+
+    /// Use the given config.yaml schema to fill in defaults for options without a value
+    pub fn with_schema(mut self, schema: ConfigSchema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Return the current value for this key.  If the key has no value the default
+    /// from the schema is returned, if a schema was given.
+    pub fn get(&self, key: &str) -> Option<String> {
+        match self.current.get(key) {
+            Some(value) => Some(value.clone()),
+            None => {
+                self.schema
+                    .as_ref()
+                    .and_then(|schema| schema.default(key))
+                    .map(|default| default.to_string())
+            }
+        }
+    }
+
+    /// All current values.  Options without a value are left out.
+    pub fn values(&self) -> &HashMap<String, String> {
+        &self.current
+    }
+
+    /// Return true if the current value for this key is different from
+    /// the previous value.  Everything counts as changed until a snapshot has been committed.
+    pub fn changed(&self, key: &str) -> bool {
+        match self.previous {
+            Some(ref previous) => previous.get(key) != self.current.get(key),
+            None => true,
+        }
+    }
+
+    /// Return previous value for this key, or None if there
+    /// is no previous value.
+    pub fn previous(&self, key: &str) -> Option<String> {
+        self.previous.as_ref().and_then(|previous| previous.get(key).cloned())
+    }
+
+    /// Return the sorted names of every option that was added, removed or modified
+    pub fn changed_keys(&self) -> Vec<String> {
+        let diff = self.diff();
+        let mut keys: Vec<String> = diff.added
+            .into_keys()
+            .chain(diff.removed.into_keys())
+            .chain(diff.modified.into_keys())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    /// Compare the current values with the previous snapshot
+    pub fn diff(&self) -> ConfigDiff {
+        let empty = HashMap::new();
+        let previous = self.previous.as_ref().unwrap_or(&empty);
+        let mut diff = ConfigDiff::default();
+        for (key, value) in &self.current {
+            match previous.get(key) {
+                Some(old) if old != value => {
+                    diff.modified.insert(key.clone(), (old.clone(), value.clone()));
+                }
+                Some(_) => {}
+                None => {
+                    diff.added.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        for (key, old) in previous {
+            if !self.current.contains_key(key) {
+                diff.removed.insert(key.clone(), old.clone());
+            }
+        }
+        diff
+    }
+
+    /// Return the names of options declared in the schema that have neither a value
+    /// nor a default.  Without a schema nothing is reported.
+    pub fn unset_options(&self) -> Vec<String> {
+        match self.schema {
+            Some(ref schema) => schema.unset_options(&self.current),
+            None => Vec::new(),
+        }
+    }
+
+    /// Save the current values as the snapshot the next hook compares against.
+    /// Hooks run through `HookRegistry` do this when they succeed, so only call it from code
+    /// that runs outside of one.
+    pub fn commit(&self) -> Result<(), JujuError> {
+        write_snapshot(&self.path, &self.current)
+    }
+}
+
+/// The types a config option can be declared with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OptionType {
+    String,
+    Int,
+    Float,
+    Boolean,
+}
+
+impl OptionType {
+    /// Returns true if the value, as printed by config-get, is valid for this type
+    pub fn accepts(&self, value: &str) -> bool {
+        match *self {
+            OptionType::String => true,
+            OptionType::Int => value.parse::<i64>().is_ok(),
+            OptionType::Float => value.parse::<f64>().is_ok(),
+            OptionType::Boolean => value == "true" || value == "false",
+        }
+    }
+}
+
+impl fmt::Display for OptionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            OptionType::String => "string",
+            OptionType::Int => "int",
+            OptionType::Float => "float",
+            OptionType::Boolean => "boolean",
+        })
+    }
+}
+
+impl FromStr for OptionType {
+    type Err = JujuError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "string" => Ok(OptionType::String),
+            "int" => Ok(OptionType::Int),
+            "float" => Ok(OptionType::Float),
+            "boolean" => Ok(OptionType::Boolean),
+            _ => Err(JujuError::Parse(format!("unknown config option type {:?}", s))),
+        }
+    }
+}
+
+/// A single option declared in config.yaml
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigOption {
+    /// The name of the option
+    pub name: String,
+    /// The declared type.  Options without a type are strings
+    pub option_type: OptionType,
+    /// The default value, formatted the way config-get prints it
+    pub default: Option<String>,
+    /// The description shown to users
+    pub description: Option<String>,
+}
+
+/// A problem found when checking config values against the schema
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigViolation {
+    /// The value could not be interpreted as the option's declared type
+    TypeMismatch {
+        key: String,
+        expected: OptionType,
+        value: String,
+    },
+    /// The key is not declared in config.yaml
+    UnknownKey(String),
+}
+
+impl fmt::Display for ConfigViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigViolation::TypeMismatch { ref key, ref expected, ref value } => {
+                write!(f, "config option {} should be {} but is {:?}", key, expected, value)
+            }
+            ConfigViolation::UnknownKey(ref key) => write!(f, "unknown config option {}", key),
+        }
+    }
+}
+
+/// The options declared in the charm's config.yaml
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigSchema {
+    options: BTreeMap<String, ConfigOption>,
+}
+
+/// Format a YAML scalar the way config-get prints it
+fn scalar_to_string(value: &Yaml) -> Option<String> {
+    match *value {
+        Yaml::String(ref s) | Yaml::Real(ref s) => Some(s.clone()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        Yaml::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+impl ConfigSchema {
+    /// Load the schema from `$CHARM_DIR/config.yaml`
+    /// # Failures
+    /// Returns a JujuError if CHARM_DIR is not set or the file cannot be read or parsed
+    pub fn load() -> Result<Self, JujuError> {
+        let charm_dir = env::var("CHARM_DIR")?;
+        ConfigSchema::from_file(&PathBuf::from(charm_dir).join("config.yaml"))
+    }
+
+    /// Load the schema from a config.yaml at the given path
+    pub fn from_file(path: &Path) -> Result<Self, JujuError> {
+        let mut s = String::new();
+        File::open(path)?.read_to_string(&mut s)?;
+        s.parse()
+    }
+
+    /// Look up a single option
+    pub fn option(&self, name: &str) -> Option<&ConfigOption> {
+        self.options.get(name)
+    }
+
+    /// All declared options, sorted by name
+    pub fn options(&self) -> Vec<&ConfigOption> {
+        self.options.values().collect()
+    }
+
+    /// The declared default for an option, if it has one
+    pub fn default(&self, name: &str) -> Option<&str> {
+        self.options.get(name).and_then(|o| o.default.as_ref()).map(|d| d.as_str())
+    }
+
+    /// Check live config values, such as those returned by `config_get_all`, against the
+    /// schema.  Returns every type mismatch and unknown key found, sorted by key.
+    pub fn validate(&self, values: &HashMap<String, String>) -> Vec<ConfigViolation> {
+        let mut keys: Vec<&String> = values.keys().collect();
+        keys.sort();
+
+        let mut violations = Vec::new();
+        for key in keys {
+            let value = &values[key];
+            match self.options.get(key) {
+                Some(option) => {
+                    if !option.option_type.accepts(value) {
+                        violations.push(ConfigViolation::TypeMismatch {
+                            key: key.clone(),
+                            expected: option.option_type,
+                            value: value.clone(),
+                        });
+                    }
+                }
+                None => violations.push(ConfigViolation::UnknownKey(key.clone())),
+            }
+        }
+        violations
+    }
+
+    /// Returns the names of options that have neither a value nor a default, sorted by name
+    pub fn unset_options(&self, values: &HashMap<String, String>) -> Vec<String> {
+        self.options
+            .values()
+            .filter(|o| o.default.is_none() && !values.contains_key(&o.name))
+            .map(|o| o.name.clone())
+            .collect()
+    }
+}
+
+impl FromStr for ConfigSchema {
+    type Err = JujuError;
+
+    /// Parse the contents of a config.yaml
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let docs = YamlLoader::load_from_str(s)?;
+        let mut options = BTreeMap::new();
+        let declared = match docs.first().map(|d| &d["options"]) {
+            Some(Yaml::Hash(declared)) => declared,
+            // A charm without any options
+            _ => return Ok(ConfigSchema { options }),
+        };
+        for (name, details) in declared {
+            let name = match name.as_str() {
+                Some(name) => name.to_string(),
+                None => return Err(JujuError::Parse(format!("invalid option name {:?}", name))),
+            };
+            let option_type = match details["type"].as_str() {
+                Some(t) => t.parse()?,
+                None => OptionType::String,
+            };
+            options.insert(name.clone(),
+                           ConfigOption {
+                               name,
+                               option_type,
+                               default: scalar_to_string(&details["default"]),
+                               description: details["description"].as_str().map(|d| d.to_string()),
+                           });
+        }
+        Ok(ConfigSchema { options })
+    }
+}
@@ -0,0 +1,193 @@
+//! The execution context Juju hands a hook through `JUJU_*` environment variables.
+//!
+//! `HookContext::from_env` reads the current process environment.  `HookContext::from_vars`
+//! builds the same context from any set of variables, which is handy in tests.
+//! # Examples
+//! ```
+//! extern crate juju;
+//! use std::collections::HashMap;
+//! use juju::HookContext;
+//!
+//! fn main() {
+//!     let mut vars = HashMap::new();
+//!     vars.insert("JUJU_HOOK_NAME", "db-relation-changed");
+//!     vars.insert("JUJU_RELATION_ID", "db:3");
+//!     vars.insert("JUJU_REMOTE_UNIT", "mysql/0");
+//!
+//!     let context = HookContext::from_vars(vars).unwrap();
+//!     assert_eq!(context.hook_name, Some("db-relation-changed".to_string()));
+//!     assert_eq!(context.relation_id.unwrap().id(), 3);
+//!     assert_eq!(context.remote_unit.unwrap().to_string(), "mysql/0");
+//!     assert!(context.action_name.is_none());
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use super::{JujuError, LogLevel};
+use super::backend;
+use super::names::{ApplicationName, RelationId, StorageId, UnitName};
+
+/// Every environment variable `HookContext` reads
+pub const VARIABLES: &[&str] = &["JUJU_HOOK_NAME",
+                                 "JUJU_DISPATCH_PATH",
+                                 "JUJU_UNIT_NAME",
+                                 "JUJU_RELATION",
+                                 "JUJU_RELATION_ID",
+                                 "JUJU_REMOTE_UNIT",
+                                 "JUJU_REMOTE_APP",
+                                 "JUJU_DEPARTING_UNIT",
+                                 "JUJU_MODEL_NAME",
+                                 "JUJU_MODEL_UUID",
+                                 "JUJU_CHARM_DIR",
+                                 "CHARM_DIR",
+                                 "JUJU_PRINCIPAL_UNIT",
+                                 "JUJU_MACHINE_ID",
+                                 "JUJU_AVAILABILITY_ZONE",
+                                 "JUJU_VERSION",
+                                 "JUJU_STORAGE_ID",
+                                 "JUJU_WORKLOAD_NAME",
+                                 "JUJU_ACTION_NAME",
+                                 "JUJU_ACTION_UUID",
+                                 "JUJU_ACTION_TAG"];
+
+/// Everything Juju tells a hook about why and where it is running.  Every field is optional
+/// because which variables are set depends on the kind of hook, ie: `remote_unit` is only set
+/// in relation hooks and `action_name` only while running an action.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HookContext {
+    /// The name of the hook being run, ie: `config-changed` (`JUJU_HOOK_NAME`)
+    pub hook_name: Option<String>,
+    /// What Juju asked the `dispatch` entry point to run, ie: `hooks/install` or
+    /// `actions/backup` (`JUJU_DISPATCH_PATH`)
+    pub dispatch_path: Option<String>,
+    /// This unit (`JUJU_UNIT_NAME`)
+    pub unit_name: Option<UnitName>,
+    /// The endpoint of the current relation hook, ie: `db` (`JUJU_RELATION`)
+    pub relation_name: Option<String>,
+    /// The relation of the current relation hook (`JUJU_RELATION_ID`)
+    pub relation_id: Option<RelationId>,
+    /// The remote unit of the current relation hook (`JUJU_REMOTE_UNIT`)
+    pub remote_unit: Option<UnitName>,
+    /// The remote application of the current relation hook (`JUJU_REMOTE_APP`)
+    pub remote_app: Option<ApplicationName>,
+    /// The unit leaving the relation in a relation-departed hook (`JUJU_DEPARTING_UNIT`)
+    pub departing_unit: Option<UnitName>,
+    /// The name of the model this unit is deployed in (`JUJU_MODEL_NAME`)
+    pub model_name: Option<String>,
+    /// The uuid of the model this unit is deployed in (`JUJU_MODEL_UUID`)
+    pub model_uuid: Option<String>,
+    /// Where the charm is unpacked (`JUJU_CHARM_DIR`, or `CHARM_DIR` on older agents)
+    pub charm_dir: Option<PathBuf>,
+    /// The principal unit, when this unit is a subordinate (`JUJU_PRINCIPAL_UNIT`)
+    pub principal_unit: Option<UnitName>,
+    /// The machine this unit runs on, ie: `0` or `0/lxd/1` (`JUJU_MACHINE_ID`)
+    pub machine_id: Option<String>,
+    /// The availability zone of the machine (`JUJU_AVAILABILITY_ZONE`)
+    pub availability_zone: Option<String>,
+    /// The version of the Juju agent running the hook, ie: `2.9.42` (`JUJU_VERSION`)
+    pub juju_version: Option<String>,
+    /// The storage instance of the current storage hook, ie: `data/0` (`JUJU_STORAGE_ID`)
+    pub storage_id: Option<StorageId>,
+    /// The workload container of the current pebble hook (`JUJU_WORKLOAD_NAME`)
+    pub workload_name: Option<String>,
+    /// The name of the action being run (`JUJU_ACTION_NAME`)
+    pub action_name: Option<String>,
+    /// The uuid of the action being run (`JUJU_ACTION_UUID`)
+    pub action_uuid: Option<String>,
+    /// The tag of the action being run, ie: `action-42` (`JUJU_ACTION_TAG`)
+    pub action_tag: Option<String>,
+}
+
+impl HookContext {
+    /// Build the context from the environment of the current process.  Only the variables
+    /// listed in `VARIABLES` are read, through `backend::var` so a `testing::Harness` can answer
+    /// them.  A variable that is not valid UTF-8 is treated as missing.
+    /// # Failures
+    /// Returns JujuError::InvalidName if JUJU_UNIT_NAME is malformed
+    pub fn from_env() -> Result<HookContext, JujuError> {
+        HookContext::from_vars(VARIABLES.iter()
+            .filter_map(|&name| backend::var(name).ok().map(|value| (name, value))))
+    }
+
+    /// Build the context from an arbitrary set of environment variables.  Variables that are
+    /// missing or empty leave their field as None.  So that one unexpected value, ie: the
+    /// `remote-<uuid>` application of a cross model relation, doesn't stop the hook from
+    /// running, a relation, remote unit, application or storage variable that doesn't parse
+    /// is logged and left as None too.
+    /// # Failures
+    /// Returns JujuError::InvalidName if JUJU_UNIT_NAME is malformed
+    pub fn from_vars<I, K, V>(vars: I) -> Result<HookContext, JujuError>
+        where I: IntoIterator<Item = (K, V)>,
+              K: Into<String>,
+              V: Into<String>
+    {
+        let vars: HashMap<String, String> = vars.into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .filter(|(_, v)| !v.is_empty())
+            .collect();
+        let string = |name: &str| vars.get(name).cloned();
+
+        Ok(HookContext {
+            hook_name: string("JUJU_HOOK_NAME"),
+            dispatch_path: string("JUJU_DISPATCH_PATH"),
+            unit_name: parse(&vars, "JUJU_UNIT_NAME")?,
+            relation_name: string("JUJU_RELATION"),
+            relation_id: parse_or_ignore(&vars, "JUJU_RELATION_ID"),
+            remote_unit: parse_or_ignore(&vars, "JUJU_REMOTE_UNIT"),
+            remote_app: parse_or_ignore(&vars, "JUJU_REMOTE_APP"),
+            departing_unit: parse_or_ignore(&vars, "JUJU_DEPARTING_UNIT"),
+            model_name: string("JUJU_MODEL_NAME"),
+            model_uuid: string("JUJU_MODEL_UUID"),
+            charm_dir: string("JUJU_CHARM_DIR").or_else(|| string("CHARM_DIR")).map(PathBuf::from),
+            principal_unit: parse_or_ignore(&vars, "JUJU_PRINCIPAL_UNIT"),
+            machine_id: string("JUJU_MACHINE_ID"),
+            availability_zone: string("JUJU_AVAILABILITY_ZONE"),
+            juju_version: string("JUJU_VERSION"),
+            storage_id: parse_or_ignore(&vars, "JUJU_STORAGE_ID"),
+            workload_name: string("JUJU_WORKLOAD_NAME"),
+            action_name: string("JUJU_ACTION_NAME"),
+            action_uuid: string("JUJU_ACTION_UUID"),
+            action_tag: string("JUJU_ACTION_TAG"),
+        })
+    }
+
+    /// True if this hook was run for a relation event
+    pub fn is_relation_hook(&self) -> bool {
+        self.relation_id.is_some()
+    }
+
+    /// True if this process was run to perform an action rather than a hook
+    pub fn is_action(&self) -> bool {
+        self.action_name.is_some() ||
+        self.dispatch_path.as_ref().map(|p| p.starts_with("actions/")).unwrap_or(false)
+    }
+}
+
+/// Like `parse`, but logs a malformed value and treats it as missing
+fn parse_or_ignore<T>(vars: &HashMap<String, String>, name: &str) -> Option<T>
+    where T: FromStr<Err = JujuError>
+{
+    match parse(vars, name) {
+        Ok(value) => value,
+        Err(e) => {
+            super::log(format!("ignoring {}", e), Some(LogLevel::Warn));
+            None
+        }
+    }
+}
+
+fn parse<T>(vars: &HashMap<String, String>, name: &str) -> Result<Option<T>, JujuError>
+    where T: FromStr<Err = JujuError>
+{
+    match vars.get(name) {
+        Some(value) => {
+            value.parse()
+                .map(Some)
+                .map_err(|e| JujuError::InvalidName(format!("{}: {}", name, e)))
+        }
+        None => Ok(None),
+    }
+}
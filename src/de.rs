@@ -0,0 +1,81 @@
+//! Deserialization of flat JSON objects, such as the output of `config-get --format=json`,
+//! into user defined structs.  Errors name the key whose value failed to deserialize.
+
+extern crate serde;
+extern crate serde_json;
+
+use self::serde::de::{self, Deserialize, DeserializeSeed, Visitor};
+use self::serde_json::{Error, Map, Value};
+use self::serde_json::map::IntoIter;
+
+/// Deserialize `T` from a JSON object.  `what` describes the keys in error messages,
+/// ie: "config option" gives errors like ``config option `port`: invalid type: ...``
+pub fn from_map<T>(map: Map<String, Value>, what: &'static str) -> Result<T, Error>
+    where T: Deserialize
+{
+    T::deserialize(KeyedDeserializer { map, what })
+}
+
+struct KeyedDeserializer {
+    map: Map<String, Value>,
+    what: &'static str,
+}
+
+impl de::Deserializer for KeyedDeserializer {
+    type Error = Error;
+
+    fn deserialize<V>(self, visitor: V) -> Result<V::Value, Error>
+        where V: Visitor
+    {
+        visitor.visit_map(KeyedMapVisitor {
+            iter: self.map.into_iter(),
+            current: None,
+            what: self.what,
+        })
+    }
+
+    forward_to_deserialize! {
+        bool u8 u16 u32 u64 i8 i16 i32 i64 f32 f64 char str string unit option
+        seq seq_fixed_size bytes byte_buf map unit_struct newtype_struct
+        tuple_struct struct struct_field tuple enum ignored_any
+    }
+}
+
+struct KeyedMapVisitor {
+    iter: IntoIter,
+    /// The entry whose key was just visited and whose value is next
+    current: Option<(String, Value)>,
+    what: &'static str,
+}
+
+impl de::MapVisitor for KeyedMapVisitor {
+    type Error = Error;
+
+    fn visit_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Error>
+        where K: DeserializeSeed
+    {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.current = Some((key.clone(), value));
+                seed.deserialize(Value::String(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn visit_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Error>
+        where V: DeserializeSeed
+    {
+        let (key, value) = match self.current.take() {
+            Some(entry) => entry,
+            None => return Err(de::Error::custom("value requested before its key")),
+        };
+        let what = self.what;
+        seed.deserialize(value)
+            .map_err(|e| de::Error::custom(format!("{} `{}`: {}", what, key, e)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
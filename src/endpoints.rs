@@ -0,0 +1,272 @@
+//! The relation endpoints a charm declares in metadata.yaml.
+//!
+//! `Metadata` parses the `provides`, `requires` and `peers` sections.  Each `Endpoint` wraps
+//! the relation hook tools so a charm can work with `db` instead of relation ids such as
+//! `db:3`.
+//! # Examples
+//! ```
+//! extern crate juju;
+//! use std::collections::HashMap;
+//! use juju::endpoints::{Metadata, Role};
+//!
+//! fn main() {
+//!     let metadata: Metadata = "
+//! name: wordpress
+//! requires:
+//!   db:
+//!     interface: mysql
+//! ".parse().unwrap();
+//!     let db = metadata.endpoint("db").unwrap();
+//!     assert_eq!(db.role, Role::Requires);
+//!
+//!     let harness = juju::testing::Harness::new("wordpress/0");
+//!     assert!(!db.is_joined().unwrap());
+//!     let id = harness.add_relation("db");
+//!     let mut data = HashMap::new();
+//!     data.insert("host".to_string(), "10.0.0.2".to_string());
+//!     harness.add_relation_unit(&id, "mysql/0", data);
+//!
+//!     let mysql: juju::UnitName = "mysql/0".parse().unwrap();
+//!     assert_eq!(db.joined_units().unwrap(), vec![mysql.clone()]);
+//!     assert_eq!(db.get(&mysql, "host").unwrap(), Some("10.0.0.2".to_string()));
+//! }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use super::JujuError;
+use super::names::{ApplicationName, RelationId, UnitName};
+
+/// Which section of metadata.yaml declares an endpoint
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    Provides,
+    Requires,
+    Peers,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Role::Provides => "provides",
+            Role::Requires => "requires",
+            Role::Peers => "peers",
+        })
+    }
+}
+
+/// A relation endpoint declared in metadata.yaml
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoint {
+    /// The endpoint name, ie: `db`
+    pub name: String,
+    pub role: Role,
+    /// The interface spoken over the endpoint, ie: `mysql`
+    pub interface: String,
+    /// `global` or `container`.  None if metadata.yaml does not say, which means global
+    pub scope: Option<String>,
+    /// The most relations the endpoint accepts, if limited
+    pub limit: Option<u64>,
+}
+
+impl Endpoint {
+    /// The relations established on this endpoint
+    /// # Failures
+    /// Returns a JujuError if relation-ids fails
+    pub fn relations(&self) -> Result<Vec<RelationId>, JujuError> {
+        super::relation_ids_by_identifier(&self.name)
+    }
+
+    /// Every remote unit joined on any relation of this endpoint
+    /// # Failures
+    /// Returns a JujuError if relation-ids or relation-list fails
+    pub fn joined_units(&self) -> Result<Vec<UnitName>, JujuError> {
+        let mut units = Vec::new();
+        for id in self.relations()? {
+            units.extend(super::relation_list_by_id(&id)?);
+        }
+        Ok(units)
+    }
+
+    /// Returns true if any remote unit is joined on this endpoint
+    /// # Failures
+    /// Returns a JujuError if relation-ids or relation-list fails
+    pub fn is_joined(&self) -> Result<bool, JujuError> {
+        for id in self.relations()? {
+            if !super::relation_list_by_id(&id)?.is_empty() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Get a single key a remote unit published on this endpoint
+    /// # Failures
+    /// Returns a JujuError if the unit is not joined on this endpoint or relation-get fails
+    pub fn get(&self, unit: &UnitName, key: &str) -> Result<Option<String>, JujuError> {
+        let id = self.relation_of(unit)?;
+        super::relation_get_by_id(key, &id, unit)
+    }
+
+    /// Get everything a remote unit published on this endpoint
+    /// # Failures
+    /// Returns a JujuError if the unit is not joined on this endpoint or relation-get fails
+    pub fn unit_data(&self, unit: &UnitName) -> Result<HashMap<String, String>, JujuError> {
+        let id = self.relation_of(unit)?;
+        super::relation_get_all_by_id(&id, unit)
+    }
+
+    /// Get everything a remote application published on this endpoint
+    /// # Failures
+    /// Returns a JujuError if none of the application's units are joined on this endpoint or
+    /// relation-get fails
+    pub fn app_data(&self, app: &ApplicationName) -> Result<HashMap<String, String>, JujuError> {
+        for id in self.relations()? {
+            if super::relation_list_by_id(&id)?.iter().any(|u| u.application() == app) {
+                return super::relation_get_app(&id, app);
+            }
+        }
+        Err(JujuError::InvalidName(format!("{} is not related on endpoint {}", app, self.name)))
+    }
+
+    /// Publish settings from this unit on every relation of this endpoint.  Keys with an
+    /// empty value are unset.
+    /// # Failures
+    /// Returns a JujuError if relation-ids or relation-set fails
+    pub fn set_unit_data(&self, settings: &HashMap<String, String>) -> Result<(), JujuError> {
+        for id in self.relations()? {
+            super::relation_set_many_by_id(settings, &id)?;
+        }
+        Ok(())
+    }
+
+    /// Publish settings from this application on every relation of this endpoint.  Keys with
+    /// an empty value are unset.
+    /// # Failures
+    /// Returns JujuError::NotLeader if this unit is not the leader, or a JujuError if
+    /// relation-ids or relation-set fails
+    pub fn set_app_data(&self, settings: &HashMap<String, String>) -> Result<(), JujuError> {
+        for id in self.relations()? {
+            super::relation_set_app(&id, settings)?;
+        }
+        Ok(())
+    }
+
+    /// The relation of this endpoint that `unit` is joined on
+    fn relation_of(&self, unit: &UnitName) -> Result<RelationId, JujuError> {
+        for id in self.relations()? {
+            if super::relation_list_by_id(&id)?.contains(unit) {
+                return Ok(id);
+            }
+        }
+        Err(JujuError::InvalidName(format!("{} is not related on endpoint {}",
+                                           unit,
+                                           self.name)))
+    }
+}
+
+/// The parts of metadata.yaml that describe the charm and its endpoints
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    /// The charm name
+    pub name: Option<String>,
+    endpoints: BTreeMap<String, Endpoint>,
+}
+
+impl Metadata {
+    /// Load `$CHARM_DIR/metadata.yaml`
+    pub fn load() -> Result<Self, JujuError> {
+        let charm_dir = env::var("CHARM_DIR")?;
+        Metadata::from_file(&PathBuf::from(charm_dir).join("metadata.yaml"))
+    }
+
+    /// Load a metadata.yaml at the given path
+    pub fn from_file(path: &Path) -> Result<Self, JujuError> {
+        let mut s = String::new();
+        File::open(path)?.read_to_string(&mut s)?;
+        s.parse()
+    }
+
+    /// Look up a single endpoint
+    pub fn endpoint(&self, name: &str) -> Option<&Endpoint> {
+        self.endpoints.get(name)
+    }
+
+    /// All declared endpoints, sorted by name
+    pub fn endpoints(&self) -> Vec<&Endpoint> {
+        self.endpoints.values().collect()
+    }
+
+    /// The endpoints declared with the given role, sorted by name
+    pub fn endpoints_with_role(&self, role: Role) -> Vec<&Endpoint> {
+        self.endpoints.values().filter(|e| e.role == role).collect()
+    }
+}
+
+impl FromStr for Metadata {
+    type Err = JujuError;
+
+    /// Parse the contents of a metadata.yaml
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let docs = YamlLoader::load_from_str(s)?;
+        let doc = match docs.first() {
+            Some(doc) => doc,
+            None => return Err(JujuError::Parse("metadata.yaml is empty".to_string())),
+        };
+        let mut endpoints = BTreeMap::new();
+        for &(section, role) in &[("provides", Role::Provides),
+                                  ("requires", Role::Requires),
+                                  ("peers", Role::Peers)] {
+            let declared = match doc[section] {
+                Yaml::Hash(ref declared) => declared,
+                _ => continue,
+            };
+            for (name, details) in declared {
+                let name = match name.as_str() {
+                    Some(name) => name.to_string(),
+                    None => {
+                        return Err(JujuError::Parse(format!("invalid endpoint name {:?}", name)))
+                    }
+                };
+                // `db: mysql` is shorthand for `db: {interface: mysql}`
+                let interface = match (details.as_str(), details["interface"].as_str()) {
+                    (Some(interface), _) |
+                    (None, Some(interface)) => interface.to_string(),
+                    (None, None) => {
+                        return Err(JujuError::Parse(format!("endpoint {} has no interface", name)))
+                    }
+                };
+                let limit = match details["limit"] {
+                    Yaml::BadValue | Yaml::Null => None,
+                    Yaml::Integer(limit) if limit >= 0 => Some(limit as u64),
+                    ref other => {
+                        let msg = format!("endpoint {} has an invalid limit {:?}", name, other);
+                        return Err(JujuError::Parse(msg));
+                    }
+                };
+                let endpoint = Endpoint {
+                    name: name.clone(),
+                    role,
+                    interface,
+                    scope: details["scope"].as_str().map(|s| s.to_string()),
+                    limit,
+                };
+                if endpoints.insert(name.clone(), endpoint).is_some() {
+                    return Err(JujuError::Parse(format!("endpoint {} is declared twice", name)));
+                }
+            }
+        }
+        Ok(Metadata {
+            name: doc["name"].as_str().map(|n| n.to_string()),
+            endpoints,
+        })
+    }
+}
@@ -9,7 +9,7 @@
 //!
 //! Our src/main.rs will contain the following:
 //! # Examples
-//! ```
+//! ```no_run
 //! #[macro_use]
 //! extern crate juju;
 //! extern crate log;
@@ -24,14 +24,8 @@
 //!     let hook_registry: Vec<juju::Hook> = vec![
 //!         hook!("config-changed", config_changed)
 //!     ];
-//!     let result =  juju::process_hooks(hook_registry);
-//!
-//!     if result.is_err(){
-//!         juju::log(&format!("Hook failed with error: {:?}", result.err()),
-//!             Some(LogLevel::Error));
-//!     }else{
-//!         juju::log("Hook call was successful!", Some(LogLevel::Debug));
-//!     }
+//!     juju::exit_on_error(juju::process_hooks(hook_registry));
+//!     juju::log("Hook call was successful!", Some(LogLevel::Debug));
 //! }
 //! ```
 //! Now you can build with `cargo build ` and install the binary in the hooks directory.
@@ -48,29 +42,51 @@
 
 extern crate charmhelpers;
 extern crate log;
-extern crate memchr;
 extern crate rusqlite;
 #[macro_use]
+extern crate serde;
+#[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate yaml_rust;
 
 use std::collections::HashMap;
 use std::env;
 use std::error::Error;
-use std::fs::OpenOptions;
 use std::fmt;
+use std::fs;
 use std::io;
-use std::io::prelude::*;
+use std::io::Write;
 use std::net::IpAddr;
-use std::path::Path;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub use log::LogLevel;
-use memchr::memchr;
-
-pub use charmhelpers::core::hookenv::log;
-
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub use backend::{HookToolBackend, HookToolOutput, ProcessBackend};
+pub use config::Config;
+pub use context::HookContext;
+pub use names::{ApplicationName, RelationId, StorageId, UnitName};
+pub use registry::{ErrorPolicy, Event, HookRegistry, RelationEvent, StorageEvent};
+
+pub mod backend;
+pub mod config;
+pub mod context;
+mod de;
+pub mod endpoints;
 pub mod macros;
+pub mod names;
+pub mod network;
+pub mod peers;
+pub mod reactive;
+pub mod registry;
+pub mod storage;
+pub mod testing;
 pub mod unitdata;
 
 // Custom error handling for the library
@@ -78,33 +94,106 @@ pub mod unitdata;
 pub enum JujuError {
     AddrParseError(std::net::AddrParseError),
     FromUtf8Error(std::string::FromUtf8Error),
+    /// A hook tool ran but exited with a non-zero exit code.  A hook tool that could not be
+    /// run at all, ie: because it is not on $PATH, is reported as an IoError instead.
+    HookTool {
+        /// The name of the tool, ie: `relation-get`
+        tool: String,
+        /// The arguments the tool was called with
+        args: Vec<String>,
+        /// The exit code.  None if the tool was terminated by a signal
+        exit_code: Option<i32>,
+        /// What the tool wrote to stderr
+        stderr: String,
+    },
+    /// A hook handler failed with the given message
+    HookFailed(String),
+    /// A relation id, unit name or application name that is not valid.  See the names module
+    InvalidName(String),
+    /// An operation was attempted in a state that doesn't allow it, ie: `unitdata::with_kv`
+    /// called from inside another `with_kv` closure
+    InvalidState(String),
+    /// A status only Juju may report, ie: Error, was passed to status_set
+    InvalidStatus(StatusType),
     IoError(io::Error),
+    /// Several handlers of the same hook failed.  See `ErrorPolicy::RunAll`
+    Multiple(Vec<JujuError>),
+    /// A hook handler panicked.  See `HookRegistry::dispatch`
+    Panic {
+        /// The panic message and where it happened
+        message: String,
+        /// The backtrace of the panicking thread
+        backtrace: String,
+    },
+    /// An operation that only the leader may perform was attempted on a non-leader unit
+    NotLeader(String),
+    /// Hook tool output or a charm file such as metadata.yaml could not be parsed, or
+    /// parsed but holds a value this crate doesn't understand
+    Parse(String),
     ParseIntError(std::num::ParseIntError),
     RusqliteError(rusqlite::Error),
     SerdeError(serde_json::Error),
+    /// No handler was registered for the action being run
+    UnknownAction(String),
+    /// No handler was registered for the hook being run
+    UnknownHook(String),
     VarError(std::env::VarError),
+    YamlError(yaml_rust::ScanError),
 }
 
 impl JujuError {
-    fn new(err: String) -> JujuError {
-        JujuError::IoError(io::Error::new(std::io::ErrorKind::Other, err))
-    }
-
     pub fn to_string(&self) -> String {
-        match *self {
-            JujuError::AddrParseError(ref err) => err.description().to_string(),
-            JujuError::FromUtf8Error(ref err) => err.description().to_string(),
-            JujuError::IoError(ref err) => err.description().to_string(),
-            JujuError::ParseIntError(ref err) => err.description().to_string(),
-            JujuError::RusqliteError(ref err) => err.description().to_string(),
-            JujuError::SerdeError(ref err) => err.description().to_string(),
-            JujuError::VarError(ref err) => err.description().to_string(),
-        }
+        format!("{}", self)
     }
 }
 impl fmt::Display for JujuError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(self.description())
+        match *self {
+            JujuError::AddrParseError(ref err) => err.fmt(f),
+            JujuError::FromUtf8Error(ref err) => err.fmt(f),
+            JujuError::HookTool { ref tool, ref args, ref exit_code, ref stderr } => {
+                write!(f, "{} {}", tool, args.join(" "))?;
+                match *exit_code {
+                    Some(code) => write!(f, " failed with exit code {}", code)?,
+                    None => write!(f, " was terminated by a signal")?,
+                }
+                if !stderr.is_empty() {
+                    write!(f, ": {}", stderr)?;
+                }
+                Ok(())
+            }
+            JujuError::HookFailed(ref msg) => f.write_str(msg),
+            JujuError::InvalidName(ref msg) => f.write_str(msg),
+            JujuError::InvalidState(ref msg) => f.write_str(msg),
+            JujuError::InvalidStatus(status_type) => {
+                write!(f, "status {} can only be set by Juju", status_type.to_string())
+            }
+            JujuError::IoError(ref err) => err.fmt(f),
+            JujuError::Multiple(ref errors) => {
+                write!(f, "{} errors: ", errors.len())?;
+                for (i, err) in errors.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str("; ")?;
+                    }
+                    err.fmt(f)?;
+                }
+                Ok(())
+            }
+            JujuError::NotLeader(ref msg) => write!(f, "not the leader: {}", msg),
+            JujuError::Panic { ref message, .. } => write!(f, "panicked: {}", message),
+            JujuError::Parse(ref msg) => f.write_str(msg),
+            JujuError::ParseIntError(ref err) => err.fmt(f),
+            JujuError::RusqliteError(ref err) => err.fmt(f),
+            JujuError::SerdeError(ref err) => err.fmt(f),
+            JujuError::UnknownAction(ref name) => {
+                write!(f, "no handler registered for action {}", name)
+            }
+            JujuError::UnknownHook(ref name) => {
+                write!(f, "no handler registered for hook {}", name)
+            }
+            JujuError::VarError(ref err) => err.fmt(f),
+            JujuError::YamlError(ref err) => err.fmt(f),
+        }
     }
 }
 
@@ -113,22 +202,46 @@ impl Error for JujuError {
         match *self {
             JujuError::AddrParseError(ref err) => err.description(),
             JujuError::FromUtf8Error(ref err) => err.description(),
+            JujuError::HookTool { .. } => "hook tool failed",
+            JujuError::HookFailed(ref msg) => msg,
+            JujuError::InvalidName(ref msg) => msg,
+            JujuError::InvalidState(ref msg) => msg,
+            JujuError::InvalidStatus(_) => "status can only be set by Juju",
             JujuError::IoError(ref err) => err.description(),
+            JujuError::Multiple(_) => "several errors",
+            JujuError::NotLeader(_) => "not the leader",
+            JujuError::Panic { .. } => "panicked",
+            JujuError::Parse(ref msg) => msg,
             JujuError::ParseIntError(ref err) => err.description(),
             JujuError::RusqliteError(ref err) => err.description(),
             JujuError::SerdeError(ref err) => err.description(),
+            JujuError::UnknownAction(_) => "no handler registered for action",
+            JujuError::UnknownHook(_) => "no handler registered for hook",
             JujuError::VarError(ref err) => err.description(),
+            JujuError::YamlError(ref err) => err.description(),
         }
     }
     fn cause(&self) -> Option<&Error> {
         match *self {
             JujuError::AddrParseError(ref err) => err.cause(),
             JujuError::FromUtf8Error(ref err) => err.cause(),
+            JujuError::HookTool { .. } => None,
+            JujuError::HookFailed(_) => None,
+            JujuError::InvalidName(_) => None,
+            JujuError::InvalidState(_) => None,
+            JujuError::InvalidStatus(_) => None,
             JujuError::IoError(ref err) => err.cause(),
+            JujuError::Multiple(ref errors) => errors.first().map(|e| e as &dyn Error),
+            JujuError::NotLeader(_) => None,
+            JujuError::Panic { .. } => None,
+            JujuError::Parse(_) => None,
             JujuError::ParseIntError(ref err) => err.cause(),
             JujuError::SerdeError(ref err) => err.cause(),
             JujuError::RusqliteError(ref err) => err.cause(),
+            JujuError::UnknownAction(_) => None,
+            JujuError::UnknownHook(_) => None,
             JujuError::VarError(ref err) => err.cause(),
+            JujuError::YamlError(ref err) => err.cause(),
         }
     }
 }
@@ -176,6 +289,12 @@ impl From<std::net::AddrParseError> for JujuError {
     }
 }
 
+impl From<yaml_rust::ScanError> for JujuError {
+    fn from(err: yaml_rust::ScanError) -> JujuError {
+        JujuError::YamlError(err)
+    }
+}
+
 
 #[derive(Debug)]
 pub enum Transport {
@@ -193,14 +312,19 @@ impl Transport {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 /// For information about what these StatusType variants mean see: [Status reference]
 /// (https://jujucharms.com/docs/stable/reference-status)
+/// Error, Unknown and Terminated are only ever reported by Juju; status_set and
+/// application_status_set reject them with JujuError::InvalidStatus.
 pub enum StatusType {
     Maintenance,
     Waiting,
     Active,
     Blocked,
+    Error,
+    Unknown,
+    Terminated,
 }
 
 impl StatusType {
@@ -211,18 +335,53 @@ impl StatusType {
             StatusType::Waiting => "waiting".to_string(),
             StatusType::Active => "active".to_string(),
             StatusType::Blocked => "blocked".to_string(),
+            StatusType::Error => "error".to_string(),
+            StatusType::Unknown => "unknown".to_string(),
+            StatusType::Terminated => "terminated".to_string(),
         }
     }
 }
 
-#[derive(Debug)]
+impl FromStr for StatusType {
+    type Err = JujuError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "maintenance" => Ok(StatusType::Maintenance),
+            "waiting" => Ok(StatusType::Waiting),
+            "active" => Ok(StatusType::Active),
+            "blocked" => Ok(StatusType::Blocked),
+            "error" => Ok(StatusType::Error),
+            "unknown" => Ok(StatusType::Unknown),
+            "terminated" => Ok(StatusType::Terminated),
+            _ => Err(JujuError::Parse(format!("unknown status type {:?}", s))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Status {
     /// The type of status
     pub status_type: StatusType,
     /// A message to show alongside the status
     pub message: String,
+    /// Extra data Juju keeps with the status, ie: why the unit is in error.  Reported by
+    /// status_get and ignored by status_set
+    pub data: HashMap<String, String>,
+}
+
+impl Status {
+    /// A status to pass to status_set, with no data
+    pub fn new<T: Into<String>>(status_type: StatusType, message: T) -> Status {
+        Status {
+            status_type,
+            message: message.into(),
+            data: HashMap::new(),
+        }
+    }
 }
 
+#[deprecated(note = "HookContext reads every JUJU_* variable and parses them into typed fields")]
 #[derive(Debug)]
 pub struct Context {
     /// The scope for the current relation hook
@@ -235,9 +394,11 @@ pub struct Context {
     pub relations: HashMap<String, String>,
 }
 
+#[allow(deprecated)]
 impl Context {
     /// Constructs a new `Context`
-    /// Creates a context that's filled out from the env variables
+    /// Creates a context that's filled out from the env variables.  See `HookContext::from_env`
+    /// for a context with every variable Juju sets.
     /// # Example usage
     /// ```
     /// extern crate juju;
@@ -248,8 +409,8 @@ impl Context {
         let relations: HashMap<String, String> = HashMap::new();
 
         // This variable is useless.  It only shows "server" for everything
-        let relation_type = env::var("JUJU_RELATION").unwrap_or("".to_string());
-        let relation_id_str = env::var("JUJU_RELATION_ID").unwrap_or("".to_string());
+        let relation_type = backend::var("JUJU_RELATION").unwrap_or("".to_string());
+        let relation_id_str = backend::var("JUJU_RELATION_ID").unwrap_or("".to_string());
         let parts: Vec<&str> = relation_id_str.split(":").collect();
         let relation_id: usize;
         if parts.len() > 1 {
@@ -257,7 +418,7 @@ impl Context {
         } else {
             relation_id = 0;
         }
-        let unit = env::var("JUJU_UNIT_NAME").unwrap_or("".to_string());
+        let unit = backend::var("JUJU_UNIT_NAME").unwrap_or("".to_string());
 
         Context {
             relation_type: relation_type,
@@ -268,109 +429,7 @@ impl Context {
     }
 }
 
-/// A HashMap representation of the charm's config.yaml, with some
-/// extra features:
-/// - See which values in the HashMap have changed since the previous hook.
-/// - For values that have changed, see what the previous value was.
-/// - Store arbitrary data for use in a later hook.
-#[derive(Debug)]
-pub struct Config {
-    values: HashMap<String, String>,
-}
-
-impl Config {
-    /// Create a new Config and automatically load the previous config values if the
-    /// .juju-persistent-config is present
-    /// The .juju-persistent-config is also saved automatically when this struct is dropped.
-    pub fn new() -> Result<Self, JujuError> {
-        if Path::new(".juju-persistent-config").exists() {
-            // Load the values from disk
-            let mut file = OpenOptions::new().read(true).open(".juju-persistent-config")?;
-            let mut s = String::new();
-            file.read_to_string(&mut s)?;
-            let previous_values: HashMap<String, String> = serde_json::from_str(&s)?;
-            Ok(Config { values: previous_values })
-        } else {
-            // Initalize with all current values
-            let current_values = config_get_all()?;
-            Ok(Config { values: current_values })
-        }
-    }
-
-    /// Return the current value for this key
-    pub fn get(self, key: &str) -> Result<Option<String>, JujuError> {
-        let current_value = config_get(key)?;
-        Ok(current_value)
-    }
-
-    /// Return true if the current value for this key is different from
-    /// the previous value.
-    pub fn changed(self, key: &str) -> Result<bool, JujuError> {
-        match self.values.get(key) {
-            Some(previous_value) => {
-                let current_value = config_get(key)?;
-                match current_value {
-                    Some(value) => Ok(&value != previous_value),
-                    None => Ok(true),
-                }
-                //Ok(&current_value != previous_value)
-            }
-            // No previous key
-            None => Ok(true),
-        }
-    }
-
-    /// Return previous value for this key, or None if there
-    /// is no previous value.
-    pub fn previous(self, key: &str) -> Option<String> {
-        match self.values.get(key) {
-            Some(previous_value) => Some(previous_value.clone()),
-            None => None,
-        }
-    }
-}
-
-impl Drop for Config {
-    // Automatic saving of the .juju-persistent-config file when this struct is dropped
-    fn drop(&mut self) {
-        let mut file = match OpenOptions::new()
-                  .write(true)
-                  .truncate(true)
-                  .create(true)
-                  .open(".juju-persistent-config") {
-            Ok(f) => f,
-            Err(e) => {
-                log(&format!("Unable to open .juju-persistent-config file for writing. Err: {}",
-                             e),
-                    Some(LogLevel::Error));
-                return;
-            }
-        };
-        let serialized = match serde_json::to_string(&self.values) {
-            Ok(f) => f,
-            Err(e) => {
-                log(&format!("Unable to serialize Config values: {:?}.  Err: {}",
-                             &self.values,
-                             e),
-                    Some(LogLevel::Error));
-                return;
-            }
-        };
-        match file.write(&serialized.as_bytes()) {
-            Ok(bytes_written) => {
-                log(&format!(".juju-persistent-config saved.  Wrote {} bytes",
-                             bytes_written),
-                    Some(LogLevel::Debug));
-            }
-            Err(e) => {
-                log(&format!("Unable to write to .juju-persistent-config Err: {}", e),
-                    Some(LogLevel::Error));
-                return;
-            }
-        }
-    }
-}
-
+#[deprecated(note = "relation functions take and return RelationId and UnitName instead")]
 #[derive(Debug)]
 pub struct Relation {
     /// The name of a unit related to your service
@@ -390,17 +449,16 @@ pub struct Hook {
     pub callback: fn() -> Result<(), String>,
 }
 
-/// Returns 0 if the process completed successfully.
-/// #Failures
-/// Returns a String of the stderr if the process failed to execute
-fn process_output(output: std::process::Output) -> Result<i32, JujuError> {
-    let status = output.status;
-
-    if status.success() {
-        return Ok(0);
-    } else {
-        return Err(JujuError::new(String::from_utf8(output.stderr)?));
+/// Write a message to the Juju debug-log at the given level
+pub fn log<T: fmt::Display>(message: T, level: Option<LogLevel>) {
+    let mut arg_list: Vec<String> = Vec::new();
+    if let Some(level) = level {
+        arg_list.push("-l".to_string());
+        arg_list.push(level.to_string());
     }
+    arg_list.push(message.to_string());
+    // Logging is best effort.  There is nowhere left to report a failure to
+    let _ = run_command("juju-log", &arg_list, false);
 }
 
 /// Add metric values
@@ -413,15 +471,15 @@ pub fn add_metric(key: &str, value: &str) -> Result<i32, JujuError> {
     let mut arg_list: Vec<String> = Vec::new();
     arg_list.push(format!("{}={}", key, value));
 
-    let output = run_command("add-metric", &arg_list, false)?;
-    return process_output(output);
+    run_command("add-metric", &arg_list, false)?;
+    Ok(0)
 }
 
 /// Get the availability zone
 /// # Failures
 /// Returns stderr if the meter_status command fails
 pub fn az_info() -> Result<String, JujuError> {
-    let az = env::var("JUJU_AVAILABILITY_ZONE")?;
+    let az = backend::var("JUJU_AVAILABILITY_ZONE")?;
     return Ok(az);
 }
 
@@ -429,7 +487,7 @@ pub fn az_info() -> Result<String, JujuError> {
 /// # Failures
 /// Returns stderr if the meter_status command fails
 pub fn meter_status() -> Result<String, JujuError> {
-    let status = env::var("JUJU_METER_STATUS")?;
+    let status = backend::var("JUJU_METER_STATUS")?;
     return Ok(status);
 }
 
@@ -438,7 +496,7 @@ pub fn meter_status() -> Result<String, JujuError> {
 /// # Failures
 /// Returns stderr if the meter_info command fails
 pub fn meter_info() -> Result<String, JujuError> {
-    let info = env::var("JUJU_METER_INFO")?;
+    let info = backend::var("JUJU_METER_INFO")?;
     return Ok(info);
 }
 
@@ -447,8 +505,8 @@ pub fn meter_info() -> Result<String, JujuError> {
 /// # Failures
 /// Returns stderr if the reboot command fails
 pub fn reboot() -> Result<i32, JujuError> {
-    let output = run_command_no_args("juju-reboot", true)?;
-    return process_output(output);
+    run_command_no_args("juju-reboot", true)?;
+    Ok(0)
 }
 /// Charm authors may trigger this command from any hook to output what
 /// version of the application is running. This could be a package version,
@@ -459,8 +517,8 @@ pub fn reboot() -> Result<i32, JujuError> {
 pub fn application_version_set(version: &str) -> Result<i32, JujuError> {
     let mut arg_list: Vec<String> = Vec::new();
     arg_list.push(version.to_string());
-    let output = run_command("application-version-set", &arg_list, false)?;
-    return process_output(output);
+    run_command("application-version-set", &arg_list, false)?;
+    Ok(0)
 }
 
 /// action_get_all gets all values that are set
@@ -502,7 +560,7 @@ pub fn action_get(key: &str) -> Result<Option<String>, JujuError> {
 /// # Failures
 /// Returns JujuError if the environment variable JUJU_ACTION_NAME does not exist
 pub fn action_name() -> Result<String, JujuError> {
-    let name = env::var("JUJU_ACTION_NAME")?;
+    let name = backend::var("JUJU_ACTION_NAME")?;
     return Ok(name);
 }
 
@@ -510,7 +568,7 @@ pub fn action_name() -> Result<String, JujuError> {
 /// # Failures
 /// Returns JujuError if the environment variable JUJU_ACTION_UUID does not exist
 pub fn action_uuid() -> Result<String, JujuError> {
-    let uuid = env::var("JUJU_ACTION_UUID")?;
+    let uuid = backend::var("JUJU_ACTION_UUID")?;
     return Ok(uuid);
 }
 
@@ -518,7 +576,7 @@ pub fn action_uuid() -> Result<String, JujuError> {
 /// # Failures
 /// Returns JujuError if the environment variable JUJU_ACTION_TAG does not exist
 pub fn action_tag() -> Result<String, JujuError> {
-    let tag = env::var("JUJU_ACTION_TAG")?;
+    let tag = backend::var("JUJU_ACTION_TAG")?;
     return Ok(tag);
 }
 
@@ -532,8 +590,8 @@ pub fn action_set(key: &str, value: &str) -> Result<i32, JujuError> {
     let mut arg_list: Vec<String> = Vec::new();
     arg_list.push(format!("{}={}", key, value));
 
-    let output = run_command("action-set", &arg_list, false)?;
-    return process_output(output);
+    run_command("action-set", &arg_list, false)?;
+    Ok(0)
 }
 
 /// See [Juju Actions](https://jujucharms.com/docs/devel/authors-charm-actions) for more
@@ -544,8 +602,8 @@ pub fn action_fail(msg: &str) -> Result<i32, JujuError> {
     let mut arg_list: Vec<String> = Vec::new();
     arg_list.push(msg.to_string());
 
-    let output = run_command("action-fail", &arg_list, false)?;
-    return process_output(output);
+    run_command("action-fail", &arg_list, false)?;
+    Ok(0)
 }
 
 /// This will return the private IP address associated with the unit.
@@ -576,6 +634,55 @@ pub fn unit_get_public_addr() -> Result<IpAddr, JujuError> {
     return Ok(ip);
 }
 
+/// Get the network configuration of an endpoint binding, ie: `db`.  When `relation_id` is
+/// given the addresses are those to use on that relation, which can differ for cross model
+/// relations.
+/// # Failures
+/// Will return a JujuError if network-get fails or its output can't be parsed
+pub fn network_get(binding: &str,
+                   relation_id: Option<&RelationId>)
+                   -> Result<network::NetworkInfo, JujuError> {
+    let mut arg_list: Vec<String> = vec![binding.to_string()];
+    if let Some(id) = relation_id {
+        arg_list.push("-r".to_string());
+        arg_list.push(id.to_string());
+    }
+    arg_list.push("--format=json".to_string());
+
+    let output = run_command("network-get", &arg_list, false)?;
+    tool_json("network-get", output)
+}
+
+/// The address remote units should use to reach this unit over the given endpoint.  Publish
+/// this rather than `unit_get_private_addr` so each relation gets an address in the
+/// endpoint's space.  Ingress addresses that are hostnames rather than IP addresses, as
+/// some providers report, are skipped; see `network_get` for all of them.
+/// # Failures
+/// Will return a JujuError if network-get fails or reports no ingress IP address
+pub fn ingress_address(endpoint: &str) -> Result<IpAddr, JujuError> {
+    let info = network_get(endpoint, None)?;
+    match info.ingress_addresses.iter().filter_map(|a| IpAddr::from_str(a).ok()).next() {
+        Some(address) => Ok(address),
+        None => Err(JujuError::Parse(format!("no ingress address for endpoint {}", endpoint))),
+    }
+}
+
+/// The address this unit should listen on for the given endpoint
+/// # Failures
+/// Will return a JujuError if network-get fails or reports no valid bind address
+pub fn bind_address(endpoint: &str) -> Result<IpAddr, JujuError> {
+    let info = network_get(endpoint, None)?;
+    let address = info.bind_addresses
+        .iter()
+        .flat_map(|bind| bind.addresses.iter())
+        .map(|a| &a.address)
+        .find(|a| !a.is_empty());
+    match address {
+        Some(address) => Ok(IpAddr::from_str(address)?),
+        None => Err(JujuError::Parse(format!("no bind address for endpoint {}", endpoint))),
+    }
+}
+
 /// This will return a configuration item that corresponds to the key passed in
 /// # Failures
 /// Will return a String of the stderr if the call fails
@@ -593,27 +700,72 @@ pub fn config_get(key: &str) -> Result<Option<String>, JujuError> {
 }
 
 /// config_get_all will return all configuration options as a HashMap<String,String>
+/// Options without a value are left out.
 /// # Failures
 /// Returns a String of if the configuration options are not able to be transformed into a HashMap
 pub fn config_get_all() -> Result<HashMap<String, String>, JujuError> {
     let mut values: HashMap<String, String> = HashMap::new();
 
-    let arg_list: Vec<String> = vec!["--all".to_string()];
-    let output = run_command("config-get", &arg_list, false)?;
-    let output_str = String::from_utf8(output.stdout)?;
-    //  Example output:
-    // "brick_paths: /mnt/brick1 /mnt/brick2\ncluster_type: Replicate\n"
-    //
-    // For each line split at : and load the parts into the HashMap
-    for line in output_str.lines() {
-        if let Some(position) = memchr(b':', &line.as_bytes()) {
-            values.insert(line[0..position].trim().to_string(),
-                          line[position + 1..].trim().to_string());
+    for (key, value) in config_get_all_json()? {
+        match value {
+            Value::Null => {}
+            Value::String(s) => {
+                values.insert(key, s);
+            }
+            other => {
+                values.insert(key, other.to_string());
+            }
         }
-        // Skip blank lines or failed splits
     }
 
-    return Ok(values);
+    Ok(values)
+}
+
+/// config_get_all_as will deserialize all configuration options into a charm defined struct.
+/// Option values keep their int, float and boolean types.  Options without a value are null so
+/// they can be deserialized into an `Option` field.
+/// # Examples
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate juju;
+///
+/// #[derive(Deserialize)]
+/// struct CharmConfig {
+///     port: u16,
+///     debug: bool,
+///     motd: Option<String>,
+/// }
+///
+/// fn main() {
+///     let harness = juju::testing::Harness::new("web/0");
+///     harness.set_config("port", 8080);
+///     harness.set_config("debug", true);
+///
+///     let config: CharmConfig = juju::config_get_all_as().unwrap();
+///     assert_eq!(config.port, 8080);
+/// }
+/// ```
+/// # Failures
+/// Returns a JujuError naming the option if a value cannot be deserialized into its field
+pub fn config_get_all_as<T>() -> Result<T, JujuError>
+    where T: Deserialize
+{
+    Ok(de::from_map(config_get_all_json()?, "config option")?)
+}
+
+/// Runs `config-get --all --format=json` and returns the resulting JSON object
+fn config_get_all_json() -> Result<serde_json::Map<String, Value>, JujuError> {
+    let arg_list: Vec<String> = vec!["--all".to_string(), "--format=json".to_string()];
+    let output = run_command("config-get", &arg_list, false)?;
+    match tool_json("config-get", output)? {
+        Value::Object(map) => Ok(map),
+        // An empty config is printed as null
+        Value::Null => Ok(serde_json::Map::new()),
+        other => {
+            Err(JujuError::Parse(format!("config-get returned {} instead of an object", other)))
+        }
+    }
 }
 
 /// This will expose a port on the unit.  The transport argument will indicate whether tcp or udp
@@ -625,8 +777,8 @@ pub fn open_port(port: usize, transport: Transport) -> Result<i32, JujuError> {
     let port_string = format!("{}/{}", port.to_string(), transport.to_string());
 
     arg_list.push(port_string);
-    let output = run_command("open-port", &arg_list, false)?;
-    return process_output(output);
+    run_command("open-port", &arg_list, false)?;
+    Ok(0)
 }
 
 /// This will hide a port on the unit.  The transport argument will indicate whether tcp or udp
@@ -638,8 +790,8 @@ pub fn close_port(port: usize, transport: Transport) -> Result<i32, JujuError> {
     let port_string = format!("{}/{}", port.to_string(), transport.to_string());
 
     arg_list.push(port_string);
-    let output = run_command("close-port", &arg_list, false)?;
-    return process_output(output);
+    run_command("close-port", &arg_list, false)?;
+    Ok(0)
 }
 
 /// Set relation information for the current unit
@@ -650,16 +802,17 @@ pub fn relation_set(key: &str, value: &str) -> Result<i32, JujuError> {
     let arg = format!("{}={}", key.clone(), value);
 
     arg_list.push(arg);
-    let output = run_command("relation-set", &arg_list, false)?;
-    return process_output(output);
+    run_command("relation-set", &arg_list, false)?;
+    Ok(0)
 }
 /// Sets relation information using a specific relation ID. Used outside of relation hooks
 /// # Failures
 /// Will return a String of the stderr if the call fails
-pub fn relation_set_by_id(key: &str, value: &str, id: &Relation) -> Result<String, JujuError> {
+pub fn relation_set_by_id(key: &str, value: &str, id: &RelationId) -> Result<String, JujuError> {
     let mut arg_list: Vec<String> = Vec::new();
 
-    arg_list.push(format!("-r {}:{}", id.name, id.id.to_string()));
+    arg_list.push("-r".to_string());
+    arg_list.push(id.to_string());
     arg_list.push(format!("{}={}", key, value).to_string());
 
     let output = run_command("relation-set", &arg_list, false)?;
@@ -667,6 +820,140 @@ pub fn relation_set_by_id(key: &str, value: &str, id: &Relation) -> Result<Strin
     return Ok(relation);
 }
 
+/// Set several relation settings for the current unit in a single relation-set call.
+/// Keys with an empty value are unset.  The settings are passed in a file so large and
+/// multi-line values are not mangled on the command line.
+/// # Failures
+/// Will return a JujuError if the settings file cannot be written or relation-set fails
+pub fn relation_set_many(settings: &HashMap<String, String>) -> Result<i32, JujuError> {
+    relation_set_file(Vec::new(), settings)
+}
+
+/// Set several relation settings using a specific relation ID in a single relation-set call.
+/// Keys with an empty value are unset.  Used outside of relation hooks
+/// # Failures
+/// Will return a JujuError if the settings file cannot be written or relation-set fails
+pub fn relation_set_many_by_id(settings: &HashMap<String, String>,
+                               id: &RelationId)
+                               -> Result<i32, JujuError> {
+    relation_set_file(vec!["-r".to_string(), id.to_string()], settings)
+}
+
+/// Get the application data bag of an application on a relation.  Pass the remote
+/// application's name to read what it published, or this unit's application name, see
+/// `local_application_name`, to read what this application published.  Only the leader
+/// may read its own application's data bag.
+/// # Failures
+/// Will return a JujuError if relation-get fails or its output cannot be parsed
+pub fn relation_get_app(id: &RelationId,
+                        app: &ApplicationName)
+                        -> Result<HashMap<String, String>, JujuError> {
+    Ok(relation_bag(relation_get_app_json(id, app)?))
+}
+
+/// Deserialize the application data bag of an application on a relation into an
+/// interface struct.  See `relation_get_app`
+/// # Failures
+/// Will return a JujuError naming the key if a value cannot be deserialized into its field
+pub fn relation_get_app_as<T>(id: &RelationId, app: &ApplicationName) -> Result<T, JujuError>
+    where T: Deserialize
+{
+    Ok(de::from_map(relation_get_app_json(id, app)?, "relation key")?)
+}
+
+/// Set several keys in this application's data bag on a relation.  Keys with an empty value
+/// are unset.  Only the leader may write the application data bag.
+/// # Failures
+/// Returns JujuError::NotLeader if this unit is not the leader, or a JujuError if
+/// relation-set fails
+pub fn relation_set_app(id: &RelationId,
+                        settings: &HashMap<String, String>)
+                        -> Result<i32, JujuError> {
+    if !is_leader()? {
+        return Err(JujuError::NotLeader(format!("cannot write the application data bag on {}",
+                                                id)));
+    }
+    relation_set_file(vec!["-r".to_string(), id.to_string(), "--app".to_string()],
+                      settings)
+}
+
+/// Serialize an interface struct into this application's data bag on a relation.
+/// String fields are written as is, None fields unset their key and anything else is
+/// written as JSON.  See `relation_set_app`
+/// # Failures
+/// Returns JujuError::NotLeader if this unit is not the leader, or a JujuError if the
+/// struct does not serialize to a map or relation-set fails
+pub fn relation_set_app_as<T>(id: &RelationId, value: &T) -> Result<i32, JujuError>
+    where T: Serialize
+{
+    let map = match serde_json::to_value(value)? {
+        Value::Object(map) => map,
+        other => {
+            return Err(JujuError::Parse(format!("relation data must be a map, not {}", other)))
+        }
+    };
+    let settings: HashMap<String, String> = map.into_iter()
+        .map(|(k, v)| match v {
+            Value::Null => (k, String::new()),
+            Value::String(s) => (k, s),
+            other => (k, other.to_string()),
+        })
+        .collect();
+    relation_set_app(id, &settings)
+}
+
+/// The name of the application this unit belongs to, ie: `wordpress` for `wordpress/0`
+/// # Failures
+/// Returns a JujuError if JUJU_UNIT_NAME is not set or is not a valid unit name
+pub fn local_application_name() -> Result<ApplicationName, JujuError> {
+    Ok(local_unit_name()?.application().clone())
+}
+
+/// The name of this unit, ie: `wordpress/0`
+/// # Failures
+/// Returns a JujuError if JUJU_UNIT_NAME is not set or is not a valid unit name
+pub fn local_unit_name() -> Result<UnitName, JujuError> {
+    backend::var("JUJU_UNIT_NAME")?.parse()
+}
+
+/// Runs relation-set with the given flags and the settings passed through `--file`.
+/// Settings often hold passwords, so the file is written to a new directory under the charm
+/// directory that only this user can read, and both are removed whether or not relation-set
+/// succeeds.
+fn relation_set_file(mut arg_list: Vec<String>,
+                     settings: &HashMap<String, String>)
+                     -> Result<i32, JujuError> {
+    static FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    // JSON is valid YAML, which is what relation-set expects
+    let json = serde_json::to_string(settings)?;
+    let base = backend::var("JUJU_CHARM_DIR")
+        .or_else(|_| backend::var("CHARM_DIR"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| env::temp_dir());
+    let dir = base.join(format!(".juju-relation-set-{}-{}",
+                                process::id(),
+                                FILE_COUNTER.fetch_add(1, Ordering::SeqCst)));
+    // Fails rather than reuse a directory, or follow a symlink, that is already there
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let path = dir.join("settings.json");
+    let result = write_private(&path, &json).and_then(|()| {
+        arg_list.push("--file".to_string());
+        arg_list.push(path.to_string_lossy().into_owned());
+        run_command("relation-set", &arg_list, false)
+    });
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_dir(&dir);
+    result?;
+    Ok(0)
+}
+
+/// Create a file readable only by this user.  Fails if the file already exists
+fn write_private(path: &Path, contents: &str) -> Result<(), JujuError> {
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
 /// Get relation information for the current unit
 /// # Failures
 /// Will return a String of the stderr if the call fails
@@ -685,10 +972,10 @@ pub fn relation_get(key: &str) -> Result<Option<String>, JujuError> {
 /// Get relation information for a specific unit
 /// # Failures
 /// Will return a String of the stderr if the call fails
-pub fn relation_get_by_unit(key: &str, unit: &Relation) -> Result<Option<String>, JujuError> {
+pub fn relation_get_by_unit(key: &str, unit: &UnitName) -> Result<Option<String>, JujuError> {
     let mut arg_list: Vec<String> = Vec::new();
     arg_list.push(key.to_string());
-    arg_list.push(format!("{}/{}", unit.name, unit.id.to_string()));
+    arg_list.push(unit.to_string());
 
     let output = run_command("relation-get", &arg_list, false)?;
     let relation = String::from_utf8(output.stdout)?.trim().to_string();
@@ -703,14 +990,15 @@ pub fn relation_get_by_unit(key: &str, unit: &Relation) -> Result<Option<String>
 /// # Failures
 /// Will return a String of the stderr if the call fails
 pub fn relation_get_by_id(key: &str,
-                          id: &Relation,
-                          unit: &Relation)
+                          id: &RelationId,
+                          unit: &UnitName)
                           -> Result<Option<String>, JujuError> {
     let mut arg_list: Vec<String> = Vec::new();
 
-    arg_list.push(format!("-r {}:{}", id.name, id.id.to_string()));
-    arg_list.push(format!("{}", key.to_string()));
-    arg_list.push(format!("{}/{}", unit.name, unit.id.to_string()));
+    arg_list.push("-r".to_string());
+    arg_list.push(id.to_string());
+    arg_list.push(key.to_string());
+    arg_list.push(unit.to_string());
 
     let output = run_command("relation-get", &arg_list, false)?;
     let relation = String::from_utf8(output.stdout)?.trim().to_string();
@@ -721,122 +1009,310 @@ pub fn relation_get_by_id(key: &str,
     }
 }
 
-/// Returns a list of all related units
+/// Get the whole relation data bag of the remote unit for the current relation hook
 /// # Failures
-/// Will return a String of the stderr if the call fails
-pub fn relation_list() -> Result<Vec<Relation>, JujuError> {
-    let mut related_units: Vec<Relation> = Vec::new();
+/// Will return a JujuError if relation-get fails or its output cannot be parsed
+pub fn relation_get_all() -> Result<HashMap<String, String>, JujuError> {
+    Ok(relation_bag(relation_get_json(None, None)?))
+}
+
+/// Get the whole relation data bag of a specific unit on the current relation
+/// # Failures
+/// Will return a JujuError if relation-get fails or its output cannot be parsed
+pub fn relation_get_all_by_unit(unit: &UnitName) -> Result<HashMap<String, String>, JujuError> {
+    Ok(relation_bag(relation_get_json(None, Some(unit))?))
+}
+
+/// Get the whole relation data bag of a unit using a specific relation ID.
+/// Used outside of relation hooks
+/// # Failures
+/// Will return a JujuError if relation-get fails or its output cannot be parsed
+pub fn relation_get_all_by_id(id: &RelationId,
+                              unit: &UnitName)
+                              -> Result<HashMap<String, String>, JujuError> {
+    Ok(relation_bag(relation_get_json(Some(id), Some(unit))?))
+}
+
+/// Deserialize the relation data bag of the remote unit for the current relation hook
+/// into an interface struct
+/// # Examples
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate juju;
+/// use std::collections::HashMap;
+///
+/// #[derive(Deserialize)]
+/// struct MysqlInterface {
+///     host: String,
+///     database: Option<String>,
+/// }
+///
+/// fn main() {
+///     let harness = juju::testing::Harness::new("wordpress/0");
+///     let id = harness.add_relation("db");
+///     let mut data = HashMap::new();
+///     data.insert("host".to_string(), "10.0.0.2".to_string());
+///     harness.add_relation_unit(&id, "mysql/0", data);
+///     harness.set_hook_relation(&id, Some("mysql/0"));
+///
+///     let mysql: MysqlInterface = juju::relation_get_as().unwrap();
+///     assert_eq!(mysql.host, "10.0.0.2");
+///     assert!(mysql.database.is_none());
+/// }
+/// ```
+/// # Failures
+/// Will return a JujuError naming the key if a value cannot be deserialized into its field
+pub fn relation_get_as<T>() -> Result<T, JujuError>
+    where T: Deserialize
+{
+    Ok(de::from_map(relation_get_json(None, None)?, "relation key")?)
+}
 
+/// Deserialize the relation data bag of a specific unit on the current relation
+/// into an interface struct
+/// # Failures
+/// Will return a JujuError naming the key if a value cannot be deserialized into its field
+pub fn relation_get_by_unit_as<T>(unit: &UnitName) -> Result<T, JujuError>
+    where T: Deserialize
+{
+    Ok(de::from_map(relation_get_json(None, Some(unit))?, "relation key")?)
+}
+
+/// Deserialize the relation data bag of a unit using a specific relation ID
+/// into an interface struct.  Used outside of relation hooks
+/// # Failures
+/// Will return a JujuError naming the key if a value cannot be deserialized into its field
+pub fn relation_get_by_id_as<T>(id: &RelationId, unit: &UnitName) -> Result<T, JujuError>
+    where T: Deserialize
+{
+    Ok(de::from_map(relation_get_json(Some(id), Some(unit))?, "relation key")?)
+}
+
+/// Runs `relation-get --format=json -` for the given relation and unit and returns
+/// the data bag as a JSON object.  Without a relation or unit Juju uses the ones of the
+/// current relation hook.
+fn relation_get_json(id: Option<&RelationId>,
+                     unit: Option<&UnitName>)
+                     -> Result<serde_json::Map<String, Value>, JujuError> {
+    let mut arg_list: Vec<String> = vec!["--format=json".to_string()];
+    if let Some(id) = id {
+        arg_list.push("-r".to_string());
+        arg_list.push(id.to_string());
+    }
+    arg_list.push("-".to_string());
+    if let Some(unit) = unit {
+        arg_list.push(unit.to_string());
+    }
+    run_relation_get_json(&arg_list)
+}
+
+/// Runs `relation-get --format=json --app -` for an application's data bag
+fn relation_get_app_json(id: &RelationId,
+                         app: &ApplicationName)
+                         -> Result<serde_json::Map<String, Value>, JujuError> {
+    let arg_list: Vec<String> = vec!["--format=json".to_string(),
+                                     "-r".to_string(),
+                                     id.to_string(),
+                                     "--app".to_string(),
+                                     "-".to_string(),
+                                     app.to_string()];
+    run_relation_get_json(&arg_list)
+}
+
+fn run_relation_get_json(arg_list: &[String])
+                         -> Result<serde_json::Map<String, Value>, JujuError> {
+    let output = run_command("relation-get", arg_list, false)?;
+    match tool_json("relation-get", output)? {
+        Value::Object(map) => Ok(map),
+        Value::Null => Ok(serde_json::Map::new()),
+        other => {
+            Err(JujuError::Parse(format!("relation-get returned {} instead of an object", other)))
+        }
+    }
+}
+
+/// Convert a relation data bag to strings.  Relation values are always strings but
+/// anything else is converted rather than dropped.
+fn relation_bag(map: serde_json::Map<String, Value>) -> HashMap<String, String> {
+    map.into_iter()
+        .map(|(k, v)| match v {
+            Value::String(s) => (k, s),
+            other => (k, other.to_string()),
+        })
+        .collect()
+}
+
+/// Returns a list of all related units
+/// # Failures
+/// Will return a JujuError if relation-list fails or returns a malformed unit name
+pub fn relation_list() -> Result<Vec<UnitName>, JujuError> {
     let output = run_command_no_args("relation-list", false)?;
     let output_str = String::from_utf8(output.stdout)?;
 
-    log(&format!("relation-list output: {}", output_str),
+    log(format!("relation-list output: {}", output_str),
         Some(LogLevel::Debug));
 
-    for line in output_str.lines() {
-        let v: Vec<&str> = line.split('/').collect();
-        let id: usize = v[1].parse::<usize>()?;
-        let r: Relation = Relation {
-            name: v[0].to_string(),
-            id: id,
-        };
-        related_units.push(r);
-    }
-    return Ok(related_units);
+    parse_lines(&output_str)
 }
 
 /// Returns a list of all related units for the supplied identifier
 /// # Failures
-/// Will return a String of the stderr if the call fails
-pub fn relation_list_by_id(id: &Relation) -> Result<Vec<Relation>, JujuError> {
-    let mut related_units: Vec<Relation> = Vec::new();
+/// Will return a JujuError if relation-list fails or returns a malformed unit name
+pub fn relation_list_by_id(id: &RelationId) -> Result<Vec<UnitName>, JujuError> {
     let mut arg_list: Vec<String> = Vec::new();
 
-    arg_list.push(format!("-r {}:{}", id.name, id.id.to_string()));
+    arg_list.push("-r".to_string());
+    arg_list.push(id.to_string());
 
     let output = run_command("relation-list", &arg_list, false)?;
     let output_str = String::from_utf8(output.stdout)?;
 
-    log(&format!("relation-list output: {}", output_str),
+    log(format!("relation-list output: {}", output_str),
         Some(LogLevel::Debug));
 
-    for line in output_str.lines() {
-        let v: Vec<&str> = line.split('/').collect();
-        let id: usize = v[1].parse::<usize>()?;
-        let r: Relation = Relation {
-            name: v[0].to_string(),
-            id: id,
-        };
-        related_units.push(r);
-    }
-    return Ok(related_units);
+    parse_lines(&output_str)
 }
 
-pub fn relation_ids() -> Result<Vec<Relation>, JujuError> {
-    let mut related_units: Vec<Relation> = Vec::new();
+/// Gets the relation IDs of the current relation hook's endpoint
+/// # Failures
+/// Will return a JujuError if relation-ids fails or returns a malformed relation id
+pub fn relation_ids() -> Result<Vec<RelationId>, JujuError> {
     let output = run_command_no_args("relation-ids", false)?;
     let output_str: String = String::from_utf8(output.stdout)?;
-    log(&format!("relation-ids output: {}", output_str),
+    log(format!("relation-ids output: {}", output_str),
         Some(LogLevel::Debug));
 
-    for line in output_str.lines() {
-        let v: Vec<&str> = line.split(':').collect();
-        let id: usize = v[1].parse::<usize>()?;
-        let r: Relation = Relation {
-            name: v[0].to_string(),
-            id: id,
-        };
-        related_units.push(r);
-    }
-    return Ok(related_units);
+    parse_lines(&output_str)
 }
 
 /// Gets the relation IDs by their identifier
 /// # Failures
-/// Will return a String of the stderr if the call fails
-
-pub fn relation_ids_by_identifier(id: &str) -> Result<Vec<Relation>, JujuError> {
-    let mut related_units: Vec<Relation> = Vec::new();
+/// Will return a JujuError if relation-ids fails or returns a malformed relation id
+pub fn relation_ids_by_identifier(id: &str) -> Result<Vec<RelationId>, JujuError> {
     let mut arg_list: Vec<String> = Vec::new();
 
     arg_list.push(id.to_string());
 
     let output = run_command("relation-ids", &arg_list, false)?;
     let output_str: String = String::from_utf8(output.stdout)?;
-    log(&format!("relation-ids output: {}", output_str),
+    log(format!("relation-ids output: {}", output_str),
         Some(LogLevel::Debug));
 
-    for line in output_str.lines() {
-        let v: Vec<&str> = line.split(':').collect();
-        let id: usize = v[1].parse::<usize>()?;
-        let r: Relation = Relation {
-            name: v[0].to_string(),
-            id: id,
-        };
-        related_units.push(r);
-    }
-    return Ok(related_units);
+    parse_lines(&output_str)
+}
+
+/// Parse each non-empty line of a hook tool's output
+fn parse_lines<T>(output: &str) -> Result<Vec<T>, JujuError>
+    where T: FromStr<Err = JujuError>
+{
+    output.lines().map(str::trim).filter(|line| !line.is_empty()).map(str::parse).collect()
 }
 
 /// Set the status of your unit to indicate to the Juju if everything is ok or something is wrong.
-/// See the Status enum for information about what can be set.
+/// See the StatusType enum for information about what can be set, and `Status::new` to build
+/// the status.
 /// # Failures
-/// Will return a String of the stderr if the call fails
+/// Returns JujuError::InvalidStatus for a status only Juju may report, or a JujuError if
+/// status-set fails
 pub fn status_set(status: Status) -> Result<i32, JujuError> {
+    settable(status.status_type)?;
     let mut arg_list: Vec<String> = Vec::new();
     arg_list.push(status.status_type.to_string());
     arg_list.push(status.message);
 
-    let output = run_command("status-set", &arg_list, false)?;
-    return process_output(output);
+    run_command("status-set", &arg_list, false)?;
+    Ok(0)
 }
 
-/// Retrieve the previously set juju workload state
+/// Set the status of the whole application, as shown by `juju status` next to the
+/// application.  Only the leader may set the application status.
 /// # Failures
-/// Will return a String of the stderr if the call fails
-pub fn status_get() -> Result<String, JujuError> {
-    let output = run_command_no_args("status-get", false)?;
-    return Ok(String::from_utf8(output.stdout)?);
+/// Returns JujuError::InvalidStatus for a status only Juju may report,
+/// JujuError::NotLeader if this unit is not the leader, or a JujuError if status-set fails
+pub fn application_status_set(status: Status) -> Result<i32, JujuError> {
+    settable(status.status_type)?;
+    if !is_leader()? {
+        return Err(JujuError::NotLeader("cannot set the application status".to_string()));
+    }
+    let arg_list: Vec<String> =
+        vec!["--application".to_string(), status.status_type.to_string(), status.message];
+    run_command("status-set", &arg_list, false)?;
+    Ok(0)
+}
+
+fn settable(status_type: StatusType) -> Result<(), JujuError> {
+    match status_type {
+        StatusType::Error | StatusType::Unknown | StatusType::Terminated => {
+            Err(JujuError::InvalidStatus(status_type))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Retrieve the previously set juju workload state of this unit
+/// # Failures
+/// Will return a JujuError if status-get fails or its output can't be parsed
+pub fn status_get() -> Result<Status, JujuError> {
+    let arg_list: Vec<String> = vec!["--format=json".to_string(), "--include-data".to_string()];
+    let output = run_command("status-get", &arg_list, false)?;
+    parse_status(tool_json("status-get", output)?)
+}
+
+/// Retrieve the status of the whole application.  Only the leader may read the application
+/// status.
+/// # Failures
+/// Returns JujuError::NotLeader if this unit is not the leader, or a JujuError if
+/// status-get fails or its output can't be parsed
+pub fn application_status_get() -> Result<Status, JujuError> {
+    if !is_leader()? {
+        return Err(JujuError::NotLeader("cannot read the application status".to_string()));
+    }
+    let arg_list: Vec<String> = vec!["--application".to_string(),
+                                     "--format=json".to_string(),
+                                     "--include-data".to_string()];
+    let output = run_command("status-get", &arg_list, false)?;
+    match tool_json("status-get", output)? {
+        Value::Object(mut map) => {
+            match map.remove("application-status") {
+                Some(status) => parse_status(status),
+                None => {
+                    Err(JujuError::Parse("status-get reported no application-status".to_string()))
+                }
+            }
+        }
+        other => {
+            Err(JujuError::Parse(format!("status-get returned {} instead of an object", other)))
+        }
+    }
+}
+
+/// Parse a `{"status": .., "message": .., "status-data": {..}}` object printed by status-get
+fn parse_status(value: Value) -> Result<Status, JujuError> {
+    let mut map = match value {
+        Value::Object(map) => map,
+        other => {
+            return Err(JujuError::Parse(format!("status-get returned {} instead of an object",
+                                              other)))
+        }
+    };
+    let status_type = match map.get("status") {
+        Some(Value::String(status)) => status.parse()?,
+        _ => return Err(JujuError::Parse("status-get reported no status".to_string())),
+    };
+    let message = match map.get("message") {
+        Some(Value::String(message)) => message.clone(),
+        _ => String::new(),
+    };
+    let data = match map.remove("status-data") {
+        Some(Value::Object(data)) => relation_bag(data),
+        _ => HashMap::new(),
+    };
+    Ok(Status {
+        status_type,
+        message,
+        data,
+    })
 }
 
 /// If storage drives were allocated to your unit this will get the path of them.
@@ -875,20 +1351,73 @@ pub fn storage_get(name: &str) -> Result<Option<String>, JujuError> {
     }
 }
 
-/// Used to list storage instances that are attached to the unit.
-/// The names returned may be passed through to storage_get
+/// Used to list storage instances that are attached to the unit, optionally only those of
+/// the given storage name, ie: `data`.
+/// The ids returned may be passed through to storage_get_all
 /// # Failures
-/// Will return a String of the stderr if the call fails
-pub fn storage_list() -> Result<String, JujuError> {
-    let output = run_command_no_args("storage-list", false)?;
-    return Ok(String::from_utf8(output.stdout)?);
+/// Will return a JujuError if storage-list fails or returns a malformed storage id
+pub fn storage_list(name: Option<&str>) -> Result<Vec<StorageId>, JujuError> {
+    let arg_list: Vec<String> = name.iter().map(|n| n.to_string()).collect();
+    let output = run_command("storage-list", &arg_list, false)?;
+    parse_lines(&String::from_utf8(output.stdout)?)
 }
 
-/// Call this to process your cmd line arguments and call any needed hooks
+/// Describe a storage instance attached to the unit
+/// # Failures
+/// Will return a JujuError if storage-get fails or doesn't report a kind and location
+pub fn storage_get_all(id: &StorageId) -> Result<storage::StorageInstance, JujuError> {
+    let arg_list: Vec<String> = vec!["-s".to_string(), id.to_string(), "--format=json".to_string()];
+    let output = run_command("storage-get", &arg_list, false)?;
+    let mut attributes = match tool_json("storage-get", output)? {
+        Value::Object(map) => relation_bag(map),
+        other => {
+            return Err(JujuError::Parse(format!("storage-get returned {} instead of an object",
+                                              other)))
+        }
+    };
+    let kind = match attributes.remove("kind") {
+        Some(kind) => kind.parse()?,
+        None => return Err(JujuError::Parse(format!("storage-get reported no kind for {}", id))),
+    };
+    let location = match attributes.remove("location") {
+        Some(location) => location,
+        None => {
+            return Err(JujuError::Parse(format!("storage-get reported no location for {}", id)))
+        }
+    };
+    Ok(storage::StorageInstance {
+        id: id.clone(),
+        kind,
+        location,
+        attributes,
+    })
+}
+
+/// Ask Juju to add `count` more instances of the given storage, ie: `data`, to this unit.
+/// The storage is attached after the current hook completes, and each instance runs its
+/// `<name>-storage-attached` hook.
+/// # Failures
+/// Will return a JujuError if storage-add fails
+pub fn storage_add(name: &str, count: usize) -> Result<i32, JujuError> {
+    let arg_list: Vec<String> = vec![format!("{}={}", name, count)];
+    run_command("storage-add", &arg_list, false)?;
+    Ok(0)
+}
+
+/// Call this to process your cmd line arguments and call any needed hooks.
+/// The hook name, see `Event::from_context`, must match a `Hook` name exactly.
+/// See `HookRegistry` for closures and pattern matching.
+/// Writes made through `unitdata::with_kv` during the hook are committed only if the
+/// hook returns Ok.
+///
+/// A panicking hook is caught: the panic and its backtrace are logged at Error level, the
+/// unit is set to Blocked and the panic is returned as an Err.  Pass the result to
+/// `exit_on_error` so that Juju marks the hook as failed.
+///
+/// Handlers registered with `reactive::register` run on every hook after the matching `Hook`.
 /// # Examples
-/// ```
+/// ```no_run
 ///     extern crate juju;
-///     extern crate log;
 ///
 ///     fn config_changed()->Result<(), String>{
 ///         //Do nothing
@@ -902,26 +1431,43 @@ pub fn storage_list() -> Result<String, JujuError> {
 ///         name: "config-changed".to_string(),
 ///         callback: config_changed,
 ///     });
-///     let result =  juju::process_hooks(hook_registry);
-///
-///     if result.is_err(){
-///         juju::log(&format!("Hook failed with error: {:?}", result.err()),
-///         Some(log::LogLevel::Error));
-///     }
+///     juju::exit_on_error(juju::process_hooks(hook_registry));
 /// ```
 ///
 pub fn process_hooks(registry: Vec<Hook>) -> Result<(), String> {
-    let hook_name = match charmhelpers::core::hookenv::hook_name() {
-        Some(s) => s,
-        _ => "".to_string(),
-    };
+    let context = HookContext::from_env().map_err(|e| e.to_string())?;
+    process_hooks_with_context(registry, &context)
+}
 
-    for hook in registry {
-        if hook_name.contains(&hook.name) {
-            return (hook.callback)();
+/// Like `process_hooks`, but runs the hook described by `context` instead of reading it from
+/// the environment, ie: to test a charm's hooks
+pub fn process_hooks_with_context(registry: Vec<Hook>,
+                                  context: &HookContext)
+                                  -> Result<(), String> {
+    let mut hooks = registry.into_iter()
+        .fold(HookRegistry::new().block_on_panic(true), HookRegistry::hook);
+    let reactive = reactive::take_registered();
+    if !reactive.is_empty() {
+        hooks = hooks.reactive(reactive);
+    }
+    match hooks.dispatch(context) {
+        Ok(()) => Ok(()),
+        Err(JujuError::HookFailed(msg)) => Err(msg),
+        Err(JujuError::UnknownHook(hook_name)) => {
+            Err(format!("Warning: Unknown callback for hook {}", hook_name))
         }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Log the error of a failed hook at Error level and exit the process with status 1, so that
+/// Juju marks the hook as failed.  Does nothing on Ok.  Works with the result of
+/// `process_hooks` as well as `HookRegistry::run`.
+pub fn exit_on_error<E: fmt::Display>(result: Result<(), E>) {
+    if let Err(e) = result {
+        log(format!("Hook failed with error: {}", e), Some(LogLevel::Error));
+        process::exit(1);
     }
-    return Err(format!("Warning: Unknown callback for hook {}", hook_name));
 }
 
 /// Juju leader get value(s)
@@ -952,8 +1498,8 @@ pub fn leader_set(settings: HashMap<String, String>) -> Result<i32, JujuError> {
         arg_list.push(format!("{}={}", key, value));
     }
 
-    let output = run_command("leader-set", &arg_list, false)?;
-    return process_output(output);
+    run_command("leader-set", &arg_list, false)?;
+    Ok(0)
 }
 
 /// Returns true/false if this unit is the leader
@@ -987,36 +1533,40 @@ pub fn is_leader() -> Result<bool, JujuError> {
     }
 }
 
-fn run_command_no_args(command: &str, as_root: bool) -> Result<std::process::Output, JujuError> {
-    if as_root {
-        let mut cmd = std::process::Command::new("sudo");
-        let output = cmd.output()?;
-        return Ok(output);
-    } else {
-        let mut cmd = std::process::Command::new(command);
-        let output = cmd.output()?;
-        return Ok(output);
-    }
+fn run_command_no_args(command: &str, as_root: bool) -> Result<HookToolOutput, JujuError> {
+    run_command(command, &[], as_root)
+}
+
+/// Runs a hook tool through the backend installed with `backend::set_backend`
+/// # Failures
+/// Returns JujuError::HookTool if the tool exits with a non-zero exit code
+/// Parse what a hook tool run with `--format=json` printed
+fn tool_json<T: Deserialize>(tool: &str, output: HookToolOutput) -> Result<T, JujuError> {
+    String::from_utf8(output.stdout)
+        .map_err(|e| e.to_string())
+        .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+        .map_err(|e| JujuError::Parse(format!("{} printed invalid JSON: {}", tool, e)))
 }
 
 fn run_command(command: &str,
-               arg_list: &Vec<String>,
+               arg_list: &[String],
                as_root: bool)
-               -> Result<std::process::Output, JujuError> {
-    if as_root {
-        let mut cmd = std::process::Command::new("sudo");
-        cmd.arg(command);
-        for arg in arg_list {
-            cmd.arg(&arg);
-        }
-        let output = cmd.output()?;
-        return Ok(output);
+               -> Result<HookToolOutput, JujuError> {
+    let output = if as_root {
+        let mut sudo_args: Vec<String> = vec![command.to_string()];
+        sudo_args.extend(arg_list.iter().cloned());
+        backend::invoke("sudo", &sudo_args)?
     } else {
-        let mut cmd = std::process::Command::new(command);
-        for arg in arg_list {
-            cmd.arg(&arg);
-        }
-        let output = cmd.output()?;
-        return Ok(output);
+        backend::invoke(command, arg_list)?
+    };
+    if output.success() {
+        Ok(output)
+    } else {
+        Err(JujuError::HookTool {
+            tool: command.to_string(),
+            args: arg_list.to_vec(),
+            exit_code: output.exit_code,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        })
     }
 }
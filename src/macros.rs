@@ -38,10 +38,7 @@ macro_rules! log {
 macro_rules! status_set {
     ($status_type:ident $message:expr) => {{
         let _ = $crate::status_set(
-            $crate::Status {
-                status_type: $crate::StatusType::$status_type,
-                message: $message.to_string()
-            }
+            $crate::Status::new($crate::StatusType::$status_type, $message.to_string())
         );
     }}
 }
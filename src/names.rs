@@ -0,0 +1,231 @@
+//! Validated names of the things hook tools talk about: relation ids such as `db:3`,
+//! unit names such as `mysql/0`, application names such as `mysql` and storage ids such as
+//! `data/0`.
+//!
+//! Each type parses with `str::parse` and prints back in the form Juju uses, so they can be
+//! passed straight to and read straight from hook tools.
+//! # Examples
+//! ```
+//! extern crate juju;
+//! use juju::{RelationId, UnitName};
+//!
+//! fn main() {
+//!     let id: RelationId = "db:3".parse().unwrap();
+//!     assert_eq!(id.endpoint(), "db");
+//!     assert_eq!(id.id(), 3);
+//!
+//!     let unit: UnitName = "mysql/0".parse().unwrap();
+//!     assert_eq!(unit.application().as_str(), "mysql");
+//!     assert_eq!(unit.to_string(), "mysql/0");
+//!
+//!     assert!("mysql".parse::<UnitName>().is_err());
+//! }
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::JujuError;
+
+fn invalid(kind: &str, value: &str, expected: &str) -> JujuError {
+    JujuError::InvalidName(format!("invalid {} {:?}: expected {}", kind, value, expected))
+}
+
+/// True if `s` is made of lowercase letters and digits, starts with a letter and uses
+/// `separators` only between such runs, ie: `wordpress`, `mysql-router` or `cluster_peers`
+fn is_name(s: &str, separators: &[char]) -> bool {
+    s.starts_with(|c: char| c.is_ascii_lowercase()) &&
+    s.split(|c| separators.contains(&c))
+        .all(|part| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        })
+}
+
+/// The name of an application, ie: `mysql`
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ApplicationName(String);
+
+impl ApplicationName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for ApplicationName {
+    type Err = JujuError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Juju also requires every hyphenated part to contain a letter, so `mysql-8` is not
+        // an application name but `mysql-8a` is
+        let valid = is_name(s, &['-']) &&
+                    s.split('-').all(|part| part.chars().any(|c| c.is_ascii_lowercase()));
+        if valid {
+            Ok(ApplicationName(s.to_string()))
+        } else {
+            Err(invalid("application name",
+                        s,
+                        "lowercase letters, digits and hyphens, starting with a letter"))
+        }
+    }
+}
+
+impl fmt::Display for ApplicationName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The name of a unit, ie: `mysql/0`
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UnitName {
+    application: ApplicationName,
+    number: usize,
+}
+
+impl UnitName {
+    pub fn new(application: ApplicationName, number: usize) -> UnitName {
+        UnitName {
+            application,
+            number,
+        }
+    }
+
+    /// The application this unit belongs to, ie: `mysql` for `mysql/0`
+    pub fn application(&self) -> &ApplicationName {
+        &self.application
+    }
+
+    /// The unit number, ie: `0` for `mysql/0`
+    pub fn number(&self) -> usize {
+        self.number
+    }
+}
+
+impl FromStr for UnitName {
+    type Err = JujuError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = "<application>/<number>";
+        let mut parts = s.splitn(2, '/');
+        let application = parts.next().unwrap_or("");
+        let number = parts.next().ok_or_else(|| invalid("unit name", s, expected))?;
+        Ok(UnitName {
+            application: application.parse()?,
+            number: number.parse().map_err(|_| invalid("unit name", s, expected))?,
+        })
+    }
+}
+
+impl fmt::Display for UnitName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.application, self.number)
+    }
+}
+
+/// The id of an established relation, ie: `db:3` for the relation with id 3 on the `db`
+/// endpoint
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RelationId {
+    endpoint: String,
+    id: usize,
+}
+
+impl RelationId {
+    /// # Failures
+    /// Returns JujuError::InvalidName if `endpoint` is not a valid endpoint name
+    pub fn new(endpoint: &str, id: usize) -> Result<RelationId, JujuError> {
+        if !is_name(endpoint, &['-', '_']) {
+            return Err(invalid("relation endpoint",
+                               endpoint,
+                               "lowercase letters, digits, hyphens and underscores, starting \
+                                with a letter"));
+        }
+        Ok(RelationId {
+            endpoint: endpoint.to_string(),
+            id,
+        })
+    }
+
+    /// The endpoint the relation was established on, ie: `db` for `db:3`
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// The numeric id of the relation, ie: `3` for `db:3`
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+impl FromStr for RelationId {
+    type Err = JujuError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = "<endpoint>:<number>";
+        let mut parts = s.splitn(2, ':');
+        let endpoint = parts.next().unwrap_or("");
+        let id = parts.next()
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| invalid("relation id", s, expected))?;
+        RelationId::new(endpoint, id)
+    }
+}
+
+impl fmt::Display for RelationId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.endpoint, self.id)
+    }
+}
+
+/// The id of a storage instance, ie: `data/0` for the first instance of the `data` storage
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StorageId {
+    name: String,
+    number: usize,
+}
+
+impl StorageId {
+    /// # Failures
+    /// Returns JujuError::InvalidName if `name` is not a valid storage name
+    pub fn new(name: &str, number: usize) -> Result<StorageId, JujuError> {
+        if !is_name(name, &['-']) {
+            return Err(invalid("storage name",
+                               name,
+                               "lowercase letters, digits and hyphens, starting with a letter"));
+        }
+        Ok(StorageId {
+            name: name.to_string(),
+            number,
+        })
+    }
+
+    /// The storage name from metadata.yaml, ie: `data` for `data/0`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The instance number, ie: `0` for `data/0`
+    pub fn number(&self) -> usize {
+        self.number
+    }
+}
+
+impl FromStr for StorageId {
+    type Err = JujuError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = "<storage name>/<number>";
+        let mut parts = s.splitn(2, '/');
+        let name = parts.next().unwrap_or("");
+        let number = parts.next()
+            .and_then(|number| number.parse().ok())
+            .ok_or_else(|| invalid("storage id", s, expected))?;
+        StorageId::new(name, number)
+    }
+}
+
+impl fmt::Display for StorageId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.name, self.number)
+    }
+}
@@ -0,0 +1,57 @@
+//! The network configuration of an endpoint binding, as reported by `network-get`.
+//!
+//! In a model with several network spaces each endpoint can be bound to a different space,
+//! so the address `unit-get private-address` reports is not necessarily the one remote units
+//! can reach.  `juju::network_get` reports the addresses for a single binding.
+//! # Examples
+//! ```
+//! extern crate juju;
+//!
+//! fn main() {
+//!     let harness = juju::testing::Harness::new("wordpress/0");
+//!     harness.set_addresses("10.0.0.2", "192.0.2.2");
+//!
+//!     let info = juju::network_get("website", None).unwrap();
+//!     assert_eq!(info.ingress_addresses, vec!["10.0.0.2".to_string()]);
+//!     assert_eq!(juju::ingress_address("website").unwrap().to_string(), "10.0.0.2");
+//! }
+//! ```
+
+/// The network configuration of a binding
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct NetworkInfo {
+    /// The interfaces the unit should listen on for the binding
+    #[serde(rename = "bind-addresses", default)]
+    pub bind_addresses: Vec<BindAddress>,
+    /// The addresses remote units should connect to, in order of preference
+    #[serde(rename = "ingress-addresses", default)]
+    pub ingress_addresses: Vec<String>,
+    /// The subnets, in CIDR notation, connections from this unit will originate from
+    #[serde(rename = "egress-subnets", default)]
+    pub egress_subnets: Vec<String>,
+}
+
+/// A network interface and the addresses it has in the binding's space
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct BindAddress {
+    /// The interface name, ie: `eth0`
+    #[serde(rename = "interface-name", default)]
+    pub interface_name: String,
+    #[serde(rename = "mac-address", default)]
+    pub mac_address: String,
+    #[serde(default)]
+    pub addresses: Vec<InterfaceAddress>,
+}
+
+/// An address of a network interface
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct InterfaceAddress {
+    /// The IP address, ie: `10.0.0.2`
+    #[serde(default)]
+    pub address: String,
+    /// The subnet the address belongs to, ie: `10.0.0.0/24`
+    #[serde(default)]
+    pub cidr: String,
+    #[serde(default)]
+    pub hostname: String,
+}
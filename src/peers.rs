@@ -0,0 +1,163 @@
+//! Cluster membership over the charm's peer relation.
+//!
+//! `Peers` finds the peer endpoint declared in metadata.yaml, lists the other units of this
+//! application with the addresses they published and publishes this unit's address and any
+//! cluster settings.  `Peers::membership_changes` remembers the members seen in unitdata,
+//! so each hook can tell which units joined or left since the previous one.
+//! # Examples
+//! ```
+//! extern crate juju;
+//! use std::collections::HashMap;
+//! use juju::endpoints::Metadata;
+//! use juju::peers::Peers;
+//!
+//! fn main() {
+//!     let metadata: Metadata = "
+//! name: gluster
+//! peers:
+//!   server:
+//!     interface: gluster-peer
+//! ".parse().unwrap();
+//!     let harness = juju::testing::Harness::new("gluster/0");
+//!     let id = harness.add_relation("server");
+//!     let mut data = HashMap::new();
+//!     data.insert("private-address".to_string(), "10.0.0.2".to_string());
+//!     harness.add_relation_unit(&id, "gluster/1", data);
+//!
+//!     let peers = Peers::from_metadata(&metadata).unwrap();
+//!     let units = peers.units().unwrap();
+//!     assert_eq!(units[0].name.to_string(), "gluster/1");
+//!     assert_eq!(units[0].address, Some("10.0.0.2".to_string()));
+//! }
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+
+use super::JujuError;
+use super::endpoints::{Endpoint, Metadata, Role};
+use super::names::UnitName;
+use super::unitdata;
+
+/// Another unit of this application, as seen over the peer relation
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerUnit {
+    pub name: UnitName,
+    /// The unit's `ingress-address`, or its `private-address` if it has none
+    pub address: Option<String>,
+    /// Everything the unit published on the peer relation
+    pub data: HashMap<String, String>,
+}
+
+/// Which peers joined and which departed since the previous call to
+/// `Peers::membership_changes`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MembershipChanges {
+    pub joined: Vec<UnitName>,
+    pub departed: Vec<UnitName>,
+}
+
+impl MembershipChanges {
+    pub fn is_empty(&self) -> bool {
+        self.joined.is_empty() && self.departed.is_empty()
+    }
+}
+
+/// The charm's peer relation.  See the module documentation
+#[derive(Clone, Debug, PartialEq)]
+pub struct Peers {
+    endpoint: Endpoint,
+}
+
+impl Peers {
+    /// Find the peer endpoint in `$CHARM_DIR/metadata.yaml`
+    /// # Failures
+    /// Returns a JujuError if metadata.yaml can't be read or declares no peer endpoint
+    pub fn load() -> Result<Peers, JujuError> {
+        Peers::from_metadata(&Metadata::load()?)
+    }
+
+    /// Find the peer endpoint in already parsed metadata.  If several are declared the first
+    /// by name is used; use `Peers::new` to pick another.
+    /// # Failures
+    /// Returns a JujuError if the metadata declares no peer endpoint
+    pub fn from_metadata(metadata: &Metadata) -> Result<Peers, JujuError> {
+        match metadata.endpoints_with_role(Role::Peers).first() {
+            Some(endpoint) => Ok(Peers::new((*endpoint).clone())),
+            None => Err(JujuError::Parse("metadata.yaml declares no peer endpoint".to_string())),
+        }
+    }
+
+    pub fn new(endpoint: Endpoint) -> Peers {
+        Peers { endpoint }
+    }
+
+    /// The peer endpoint
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Every other unit of this application that has joined the peer relation
+    /// # Failures
+    /// Returns a JujuError if relation-ids, relation-list or relation-get fails
+    pub fn units(&self) -> Result<Vec<PeerUnit>, JujuError> {
+        let mut units = Vec::new();
+        for id in self.endpoint.relations()? {
+            for name in super::relation_list_by_id(&id)? {
+                let data = super::relation_get_all_by_id(&id, &name)?;
+                let address = data.get("ingress-address")
+                    .or_else(|| data.get("private-address"))
+                    .cloned();
+                units.push(PeerUnit {
+                    name,
+                    address,
+                    data,
+                });
+            }
+        }
+        Ok(units)
+    }
+
+    /// Publish the address peers should use to reach this unit as `private-address`.  That is
+    /// the peer endpoint's ingress address, or the unit's private address if network-get
+    /// can't report one, ie: on Juju versions without network-get.
+    /// # Failures
+    /// Returns a JujuError if both network-get and unit-get fail or relation-set fails
+    pub fn publish_address(&self) -> Result<(), JujuError> {
+        let address = match super::ingress_address(&self.endpoint.name) {
+            Ok(address) => address,
+            Err(_) => super::unit_get_private_addr()?,
+        };
+        let mut settings = HashMap::new();
+        settings.insert("private-address".to_string(), address.to_string());
+        self.publish(&settings)
+    }
+
+    /// Publish cluster settings from this unit to its peers.  Keys with an empty value are
+    /// unset.
+    /// # Failures
+    /// Returns a JujuError if relation-ids or relation-set fails
+    pub fn publish(&self, settings: &HashMap<String, String>) -> Result<(), JujuError> {
+        self.endpoint.set_unit_data(settings)
+    }
+
+    /// Compare the peers joined now with those seen by the previous call, and remember the
+    /// current members for next time.  The first call reports every peer as joined.  Because
+    /// the members are kept in unitdata, the comparison is rolled back if the hook fails.
+    /// # Failures
+    /// Returns a JujuError if relation-ids or relation-list fails or unitdata can't be used
+    pub fn membership_changes(&self) -> Result<MembershipChanges, JujuError> {
+        let current: BTreeSet<UnitName> = self.endpoint.joined_units()?.into_iter().collect();
+        let key = format!("peers.{}.members", self.endpoint.name);
+        let previous: Vec<String> = unitdata::with_kv(|kv| kv.get(&key))?.unwrap_or_default();
+        let previous = previous.iter()
+            .map(|unit| unit.parse())
+            .collect::<Result<BTreeSet<UnitName>, JujuError>>()?;
+
+        let members: Vec<String> = current.iter().map(|unit| unit.to_string()).collect();
+        unitdata::with_kv(|kv| kv.set(&key, members))?;
+        Ok(MembershipChanges {
+            joined: current.difference(&previous).cloned().collect(),
+            departed: previous.difference(&current).cloned().collect(),
+        })
+    }
+}
@@ -0,0 +1,264 @@
+//! A flag based handler framework in the style of charms.reactive.
+//!
+//! Handlers declare the flags they need with `when`, `when_not` and `when_any`.  On every
+//! hook `Reactive::dispatch` refreshes the automatic flags, then keeps running the first
+//! handler whose conditions hold until no handler is left to run.  Each handler runs at
+//! most once per hook.  Flags are stored with `unitdata::with_kv`, so they persist between
+//! hooks and are rolled back with the rest of the unit's data if the hook fails.
+//!
+//! The automatic flags are:
+//!
+//! * `config.changed` and `config.changed.<key>` when config changed since the last hook.
+//!   These are cleared again at the end of the hook.
+//! * `endpoint.<name>.joined` while any remote unit is related on the endpoint
+//! * `endpoint.<name>.changed` when a remote unit's data on the endpoint changed.  This
+//!   stays set until a handler clears it.
+//! * `leadership.is_leader` while this unit is the leader
+//!
+//! Endpoint flags are maintained for every endpoint a handler mentions in its conditions.
+//! # Examples
+//! ```
+//! extern crate juju;
+//! use juju::HookRegistry;
+//! use juju::reactive::{self, Reactive, ReactiveHandler};
+//!
+//! fn main() {
+//!     let reactive = Reactive::new()
+//!         .handler(ReactiveHandler::new("install", |_| {
+//!                 // Install the workload, then never run this handler again
+//!                 reactive::set_flag("app.installed")
+//!             })
+//!             .when_not("app.installed"))
+//!         .handler(ReactiveHandler::new("configure", |_| Ok(()))
+//!             .when("app.installed")
+//!             .when("config.changed"));
+//!     let _registry = HookRegistry::new().reactive(reactive);
+//! }
+//! ```
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+use super::{JujuError, LogLevel};
+use super::context::HookContext;
+use super::registry::Handler;
+use super::unitdata;
+
+const FLAG_PREFIX: &str = "reactive.flags.";
+/// The automatic flags keep their own snapshots so that a charm calling
+/// `Storage::snapshot_config` or `snapshot_relation` itself doesn't hide changes from them
+const CONFIG_PREFIX: &str = "reactive.config.";
+const RELATION_PREFIX: &str = "reactive.rels.";
+
+fn flag_key(flag: &str) -> String {
+    format!("{}{}", FLAG_PREFIX, flag)
+}
+
+/// Set a flag.  It stays set, across hooks, until it is cleared
+pub fn set_flag(flag: &str) -> Result<(), JujuError> {
+    unitdata::with_kv(|kv| kv.set(&flag_key(flag), true))
+}
+
+/// Clear a flag.  Clearing a flag that is not set does nothing
+pub fn clear_flag(flag: &str) -> Result<(), JujuError> {
+    unitdata::with_kv(|kv| kv.unset(&flag_key(flag)))
+}
+
+/// Returns true if the flag is set
+pub fn is_flag_set(flag: &str) -> Result<bool, JujuError> {
+    unitdata::with_kv(|kv| Ok(kv.get::<bool>(&flag_key(flag))?.is_some()))
+}
+
+/// Returns every flag that is set
+pub fn get_flags() -> Result<BTreeSet<String>, JujuError> {
+    let flags = unitdata::with_kv(|kv| kv.getrange(FLAG_PREFIX, true))?;
+    Ok(flags.into_keys().collect())
+}
+
+/// A handler together with the flags it needs to run
+pub struct ReactiveHandler {
+    name: String,
+    when: Vec<String>,
+    when_not: Vec<String>,
+    when_any: Vec<Vec<String>>,
+    callback: Handler,
+}
+
+impl ReactiveHandler {
+    /// A handler without conditions, which runs on every hook.  `name` is used in logs.
+    pub fn new<F>(name: &str, callback: F) -> ReactiveHandler
+        where F: FnMut(&HookContext) -> Result<(), JujuError> + 'static
+    {
+        ReactiveHandler {
+            name: name.to_string(),
+            when: Vec::new(),
+            when_not: Vec::new(),
+            when_any: Vec::new(),
+            callback: Box::new(callback),
+        }
+    }
+
+    /// Only run while `flag` is set
+    pub fn when(mut self, flag: &str) -> ReactiveHandler {
+        self.when.push(flag.to_string());
+        self
+    }
+
+    /// Only run while `flag` is not set
+    pub fn when_not(mut self, flag: &str) -> ReactiveHandler {
+        self.when_not.push(flag.to_string());
+        self
+    }
+
+    /// Only run while at least one of `flags` is set
+    pub fn when_any(mut self, flags: &[&str]) -> ReactiveHandler {
+        self.when_any.push(flags.iter().map(|f| f.to_string()).collect());
+        self
+    }
+
+    fn ready(&self, flags: &BTreeSet<String>) -> bool {
+        self.when.iter().all(|f| flags.contains(f)) &&
+        !self.when_not.iter().any(|f| flags.contains(f)) &&
+        self.when_any.iter().all(|any| any.iter().any(|f| flags.contains(f)))
+    }
+
+    /// Every flag this handler's conditions mention
+    fn flags(&self) -> Vec<&String> {
+        self.when
+            .iter()
+            .chain(self.when_not.iter())
+            .chain(self.when_any.iter().flat_map(|any| any.iter()))
+            .collect()
+    }
+}
+
+/// A set of reactive handlers.  See the module documentation
+#[derive(Default)]
+pub struct Reactive {
+    handlers: Vec<ReactiveHandler>,
+}
+
+impl Reactive {
+    pub fn new() -> Reactive {
+        Reactive::default()
+    }
+
+    /// Add a handler.  When several handlers are ready the one added first runs first
+    pub fn handler(mut self, handler: ReactiveHandler) -> Reactive {
+        self.handlers.push(handler);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// Refresh the automatic flags and run handlers until none is left whose conditions hold
+    /// # Failures
+    /// Returns the first error of a hook tool call or a handler.  Handlers after a failing
+    /// one are not run
+    pub fn dispatch(&mut self, context: &HookContext) -> Result<(), JujuError> {
+        let transient = refresh_automatic_flags(context, &self.endpoints())?;
+        let mut ran = vec![false; self.handlers.len()];
+        loop {
+            let flags = get_flags()?;
+            let next = self.handlers
+                .iter()
+                .enumerate()
+                .position(|(i, handler)| !ran[i] && handler.ready(&flags));
+            let i = match next {
+                Some(i) => i,
+                None => break,
+            };
+            ran[i] = true;
+            let handler = &mut self.handlers[i];
+            super::log(format!("reactive: running handler {}", handler.name),
+                       Some(LogLevel::Debug));
+            (handler.callback)(context)?;
+        }
+        for flag in transient {
+            clear_flag(&flag)?;
+        }
+        Ok(())
+    }
+
+    /// The endpoints whose flags a handler mentions, ie: `db` for `endpoint.db.joined`
+    fn endpoints(&self) -> BTreeSet<String> {
+        self.handlers
+            .iter()
+            .flat_map(|h| h.flags())
+            .filter_map(|flag| {
+                let mut parts = flag.splitn(3, '.');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some("endpoint"), Some(name), Some(_)) => Some(name.to_string()),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Set the automatic flags for this hook.  Returns the flags that only last for this hook
+fn refresh_automatic_flags(context: &HookContext,
+                           endpoints: &BTreeSet<String>)
+                           -> Result<Vec<String>, JujuError> {
+    let mut transient = Vec::new();
+    let config = super::config_get_all()?;
+    let changed = unitdata::with_kv(|kv| kv.snapshot(config, CONFIG_PREFIX))?;
+    if !changed.is_empty() {
+        transient.push("config.changed".to_string());
+    }
+    transient.extend(changed.into_keys().map(|key| format!("config.changed.{}", key)));
+    for flag in &transient {
+        set_flag(flag)?;
+    }
+
+    if super::is_leader()? {
+        set_flag("leadership.is_leader")?;
+    } else {
+        clear_flag("leadership.is_leader")?;
+    }
+
+    let mut endpoints = endpoints.clone();
+    if let Some(ref name) = context.relation_name {
+        endpoints.insert(name.clone());
+    }
+    for endpoint in endpoints {
+        let mut joined = false;
+        let mut changed = false;
+        for id in super::relation_ids_by_identifier(&endpoint)? {
+            for unit in super::relation_list_by_id(&id)? {
+                joined = true;
+                let data = super::relation_get_all_by_id(&id, &unit)?;
+                let prefix = format!("{}{}.{}.", RELATION_PREFIX, id, unit);
+                let delta = unitdata::with_kv(|kv| kv.snapshot(data, &prefix))?;
+                changed |= !delta.is_empty();
+            }
+        }
+        let joined_flag = format!("endpoint.{}.joined", endpoint);
+        if joined {
+            set_flag(&joined_flag)?;
+        } else {
+            clear_flag(&joined_flag)?;
+        }
+        if changed {
+            set_flag(&format!("endpoint.{}.changed", endpoint))?;
+        }
+    }
+    Ok(transient)
+}
+
+thread_local! {
+    static REGISTERED: RefCell<Reactive> = RefCell::new(Reactive::new());
+}
+
+/// Register a handler to be run by `process_hooks` on every hook, after the matching `Hook`.
+/// Handlers are registered for the current thread.
+pub fn register(handler: ReactiveHandler) {
+    REGISTERED.with(|r| r.borrow_mut().handlers.push(handler));
+}
+
+/// Take every handler registered with `register`, leaving none registered
+pub fn take_registered() -> Reactive {
+    REGISTERED.with(|r| r.replace(Reactive::new()))
+}
@@ -0,0 +1,597 @@
+//! Register closures for the hooks a charm handles and dispatch the current hook to them.
+//!
+//! Unlike `process_hooks`, handlers can capture state and are handed the parsed
+//! `HookContext` instead of reading the environment themselves.
+//!
+//! The registry works with per-hook symlinks as well as with Juju's `dispatch` entry point.
+//! When Juju runs `dispatch` it sets JUJU_DISPATCH_PATH to `hooks/<hook>` or
+//! `actions/<action>`, so the charm's binary can be installed as `dispatch` and handle every
+//! hook and action itself.
+//! # Examples
+//! ```
+//! extern crate juju;
+//! use std::cell::Cell;
+//! use std::rc::Rc;
+//! use juju::{HookContext, HookRegistry};
+//!
+//! fn main() {
+//!     let changes = Rc::new(Cell::new(0));
+//!     let counter = changes.clone();
+//!     let mut registry = HookRegistry::new()
+//!         .on("install", |_| Ok(()))
+//!         .on_pattern("*-relation-changed", move |context| {
+//!             juju::log(format!("{:?} changed", context.remote_unit), None);
+//!             counter.set(counter.get() + 1);
+//!             Ok(())
+//!         });
+//!
+//!     let context = HookContext::from_vars(vec![("JUJU_HOOK_NAME", "db-relation-changed")])
+//!         .unwrap();
+//!     let _harness = juju::testing::Harness::new("wordpress/0");
+//!     registry.dispatch(&context).unwrap();
+//!     assert_eq!(changes.get(), 1);
+//! }
+//! ```
+
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Once;
+
+use charmhelpers::core::hookenv;
+
+use super::{Hook, JujuError, LogLevel, Status, StatusType};
+use super::config;
+use super::context::HookContext;
+use super::reactive::Reactive;
+use super::storage::StorageInstance;
+use super::unitdata;
+
+/// A hook handler.  Gets the context of the hook being run
+pub type Handler = Box<dyn FnMut(&HookContext) -> Result<(), JujuError>>;
+
+/// Middleware run after the handlers of every hook.  Gets the errors the hook produced so
+/// far, which is empty if it succeeded.
+pub type AfterHandler = Box<dyn FnMut(&HookContext, &[JujuError]) -> Result<(), JujuError>>;
+
+/// What `dispatch` does when a handler fails
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ErrorPolicy {
+    /// Skip the remaining handlers and return the error.  This is the default
+    #[default]
+    StopOnFirstError,
+    /// Run the remaining handlers anyway and return every error
+    RunAll,
+}
+
+
+/// How a registration decides whether it handles a hook
+#[derive(Clone, Debug, PartialEq)]
+enum Matcher {
+    /// The hook name must be exactly this
+    Exact(String),
+    /// A glob where `*` matches any run of characters, ie: `*-relation-changed`
+    Pattern(String),
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Matcher::Exact(ref name) => f.write_str(name),
+            Matcher::Pattern(ref pattern) => write!(f, "pattern {}", pattern),
+        }
+    }
+}
+
+impl Matcher {
+    fn matches(&self, hook_name: &str) -> bool {
+        match *self {
+            Matcher::Exact(ref name) => name == hook_name,
+            Matcher::Pattern(ref pattern) => glob_match(pattern, hook_name),
+        }
+    }
+}
+
+/// The hooks Juju runs for each relation, ie: `db-relation-joined`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RelationEvent {
+    Created,
+    Joined,
+    Changed,
+    Departed,
+    Broken,
+}
+
+impl fmt::Display for RelationEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            RelationEvent::Created => "created",
+            RelationEvent::Joined => "joined",
+            RelationEvent::Changed => "changed",
+            RelationEvent::Departed => "departed",
+            RelationEvent::Broken => "broken",
+        })
+    }
+}
+
+/// The hooks Juju runs for each storage instance, ie: `data-storage-attached`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StorageEvent {
+    Attached,
+    Detaching,
+}
+
+impl fmt::Display for StorageEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            StorageEvent::Attached => "attached",
+            StorageEvent::Detaching => "detaching",
+        })
+    }
+}
+
+/// What Juju ran this binary for
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A hook, ie: `install`
+    Hook(String),
+    /// An action, ie: `backup`
+    Action(String),
+}
+
+impl Event {
+    /// Resolve the event from a dispatch path such as `hooks/install` or `actions/backup`,
+    /// or from the path of a hook or action symlink such as `/var/lib/juju/.../hooks/install`
+    pub fn from_path(path: &str) -> Event {
+        let path = Path::new(path);
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => String::new(),
+        };
+        let dir = path.parent().and_then(|p| p.file_name());
+        if dir.map(|d| d == "actions").unwrap_or(false) {
+            Event::Action(name)
+        } else {
+            Event::Hook(name)
+        }
+    }
+
+    /// The current event.  In order of preference this comes from JUJU_DISPATCH_PATH,
+    /// JUJU_ACTION_NAME, JUJU_HOOK_NAME and finally the path this binary was run as.
+    pub fn from_context(context: &HookContext) -> Event {
+        if let Some(ref path) = context.dispatch_path {
+            return Event::from_path(path);
+        }
+        if let Some(ref action) = context.action_name {
+            return Event::Action(action.clone());
+        }
+        if let Some(ref hook) = context.hook_name {
+            return Event::Hook(hook.clone());
+        }
+        Event::from_path(&hookenv::hook_name().unwrap_or_default())
+    }
+
+    /// The hook or action name
+    pub fn name(&self) -> &str {
+        match *self {
+            Event::Hook(ref name) |
+            Event::Action(ref name) => name,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Event::Hook(ref name) => write!(f, "hook {}", name),
+            Event::Action(ref name) => write!(f, "action {}", name),
+        }
+    }
+}
+
+struct Registration {
+    matchers: Vec<Matcher>,
+    /// True if this handles actions rather than hooks
+    action: bool,
+    handler: Handler,
+}
+
+impl Registration {
+    fn matcher_for(&self, event: &Event) -> Option<&Matcher> {
+        let action = match *event {
+            Event::Hook(_) => false,
+            Event::Action(_) => true,
+        };
+        if action != self.action {
+            return None;
+        }
+        self.matchers.iter().find(|m| m.matches(event.name()))
+    }
+}
+
+/// A set of hook handlers built up with `on`, `on_many`, `on_pattern`, `on_relation`,
+/// `on_storage` and `on_storage_instance`.  Hook names are matched exactly unless registered
+/// as a pattern.  `dispatch` runs every registration that matches the current hook, in the
+/// order they were registered, between the `before` and `after` middleware.
+#[derive(Default)]
+pub struct HookRegistry {
+    registrations: Vec<Registration>,
+    /// Reactive dispatchers, run on every hook after the registrations
+    reactive: Vec<Handler>,
+    before: Vec<Handler>,
+    after: Vec<AfterHandler>,
+    error_policy: ErrorPolicy,
+    block_on_panic: bool,
+}
+
+impl HookRegistry {
+    pub fn new() -> HookRegistry {
+        HookRegistry::default()
+    }
+
+    /// Handle the hook with exactly this name, ie: `config-changed`
+    pub fn on<F>(self, hook_name: &str, handler: F) -> HookRegistry
+        where F: FnMut(&HookContext) -> Result<(), JujuError> + 'static
+    {
+        self.register(vec![Matcher::Exact(hook_name.to_string())], Box::new(handler))
+    }
+
+    /// Handle several hooks with the same handler, ie: `install` and `upgrade-charm`
+    pub fn on_many<F>(self, hook_names: &[&str], handler: F) -> HookRegistry
+        where F: FnMut(&HookContext) -> Result<(), JujuError> + 'static
+    {
+        let matchers = hook_names.iter().map(|name| Matcher::Exact(name.to_string())).collect();
+        self.register(matchers, Box::new(handler))
+    }
+
+    /// Handle every hook matching a glob, where `*` matches any run of characters,
+    /// ie: `*-relation-changed` or `data-storage-*`
+    pub fn on_pattern<F>(self, pattern: &str, handler: F) -> HookRegistry
+        where F: FnMut(&HookContext) -> Result<(), JujuError> + 'static
+    {
+        self.register(vec![Matcher::Pattern(pattern.to_string())], Box::new(handler))
+    }
+
+    /// Handle a relation hook.  `endpoint` may be a glob, so `on_relation("*",
+    /// RelationEvent::Departed, ..)` handles `relation-departed` for every endpoint.
+    pub fn on_relation<F>(self, endpoint: &str, event: RelationEvent, handler: F) -> HookRegistry
+        where F: FnMut(&HookContext) -> Result<(), JujuError> + 'static
+    {
+        let name = format!("{}-relation-{}", endpoint, event);
+        self.register(vec![matcher(name)], Box::new(handler))
+    }
+
+    /// Handle a storage hook.  `storage` may be a glob, ie: `*` for every storage name.
+    pub fn on_storage<F>(self, storage: &str, event: StorageEvent, handler: F) -> HookRegistry
+        where F: FnMut(&HookContext) -> Result<(), JujuError> + 'static
+    {
+        let name = format!("{}-storage-{}", storage, event);
+        self.register(vec![matcher(name)], Box::new(handler))
+    }
+
+    /// Handle a storage hook with the storage instance it was run for, described by
+    /// `storage_get_all` from JUJU_STORAGE_ID.  `storage` may be a glob as for `on_storage`.
+    pub fn on_storage_instance<F>(self,
+                                  storage: &str,
+                                  event: StorageEvent,
+                                  mut handler: F)
+                                  -> HookRegistry
+        where F: FnMut(&HookContext, &StorageInstance) -> Result<(), JujuError> + 'static
+    {
+        self.on_storage(storage, event, move |context| {
+            let id = match context.storage_id {
+                Some(ref id) => id,
+                None => {
+                    let msg = "storage hook was run without JUJU_STORAGE_ID".to_string();
+                    return Err(JujuError::Parse(msg));
+                }
+            };
+            handler(context, &super::storage_get_all(id)?)
+        })
+    }
+
+    /// Handle the action with exactly this name, ie: `backup`.  If the handler fails the
+    /// action is marked as failed with the error as its message.
+    pub fn on_action<F>(mut self, action: &str, handler: F) -> HookRegistry
+        where F: FnMut(&HookContext) -> Result<(), JujuError> + 'static
+    {
+        self.registrations.push(Registration {
+            matchers: vec![Matcher::Exact(action.to_string())],
+            action: true,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Run `middleware` before the handlers of every hook and action, ie: to set a
+    /// maintenance status.  If it fails the handlers are skipped.
+    pub fn before<F>(mut self, middleware: F) -> HookRegistry
+        where F: FnMut(&HookContext) -> Result<(), JujuError> + 'static
+    {
+        self.before.push(Box::new(middleware));
+        self
+    }
+
+    /// Run `middleware` after the handlers of every hook and action, ie: to log timing or
+    /// refresh the unit's status.  It always runs, even when a handler failed, and is handed
+    /// the errors so far.
+    pub fn after<F>(mut self, middleware: F) -> HookRegistry
+        where F: FnMut(&HookContext, &[JujuError]) -> Result<(), JujuError> + 'static
+    {
+        self.after.push(Box::new(middleware));
+        self
+    }
+
+    /// Choose whether a failing handler stops the remaining handlers, see `ErrorPolicy`
+    pub fn error_policy(mut self, policy: ErrorPolicy) -> HookRegistry {
+        self.error_policy = policy;
+        self
+    }
+
+    /// Set the unit's status to Blocked with a short summary when a handler or middleware
+    /// panics.  Panics are always caught, logged with their backtrace and returned as
+    /// JujuError::Panic.
+    pub fn block_on_panic(mut self, block: bool) -> HookRegistry {
+        self.block_on_panic = block;
+        self
+    }
+
+    /// Run reactive handlers on every hook, after every other handler of the hook.  They are
+    /// kept apart from the other registrations, so they never count as overlapping them and
+    /// are not listed by `matching`.  See the reactive module
+    pub fn reactive(mut self, mut reactive: Reactive) -> HookRegistry {
+        self.reactive.push(Box::new(move |context| reactive.dispatch(context)));
+        self
+    }
+
+    /// Register a `Hook`, such as one made with the `hook!` macro.  An error returned by its
+    /// callback is reported as JujuError::HookFailed.
+    pub fn hook(self, hook: Hook) -> HookRegistry {
+        let callback = hook.callback;
+        self.register(vec![Matcher::Exact(hook.name)],
+                      Box::new(move |_: &HookContext| callback().map_err(JujuError::HookFailed)))
+    }
+
+    fn register(mut self, matchers: Vec<Matcher>, handler: Handler) -> HookRegistry {
+        for matcher in &matchers {
+            self.warn_overlaps(matcher);
+        }
+        self.registrations.push(Registration {
+            matchers,
+            action: false,
+            handler,
+        });
+        self
+    }
+
+    /// Build the context from the environment and dispatch the current hook or action.  Pass
+    /// the result to `juju::exit_on_error` so that a failed hook exits with a non-zero code.
+    /// # Failures
+    /// Returns a JujuError if the environment cannot be parsed, see `dispatch` for the rest
+    pub fn run(&mut self) -> Result<(), JujuError> {
+        let context = HookContext::from_env()?;
+        self.dispatch(&context)
+    }
+
+    /// Run the handlers registered for the hook or action described by `context`, see
+    /// `Event::from_context`.  Writes made through `unitdata::with_kv` during the hook, and the
+    /// snapshot of any `Config` loaded during it, are committed only if it succeeds.  A failed
+    /// action is marked as failed with the error as its message.  If several registrations
+    /// match, all of them run in the order they were registered.
+    /// A panicking handler or middleware is treated as a failing one, see `block_on_panic`.
+    /// # Failures
+    /// Returns JujuError::UnknownHook or JujuError::UnknownAction if no handler matches.
+    /// Otherwise returns the error of the failing handler or middleware, or
+    /// JujuError::Multiple if there was more than one or action-fail failed as well.
+    pub fn dispatch(&mut self, context: &HookContext) -> Result<(), JujuError> {
+        let event = Event::from_context(context);
+        let is_hook = match event {
+            Event::Hook(_) => true,
+            Event::Action(_) => false,
+        };
+        let runs_reactive = is_hook && !self.reactive.is_empty();
+        if self.matching(&event).is_empty() && !runs_reactive {
+            return Err(match event {
+                Event::Hook(name) => JujuError::UnknownHook(name),
+                Event::Action(name) => JujuError::UnknownAction(name),
+            });
+        }
+
+        unitdata::begin_hook()?;
+        config::begin_hook();
+        let block = self.block_on_panic;
+        let mut errors = Vec::new();
+        for before in &mut self.before {
+            if let Err(e) = guarded(&event, block, || before(context)) {
+                errors.push(e);
+                break;
+            }
+        }
+        if errors.is_empty() {
+            let policy = self.error_policy;
+            let handlers = self.registrations
+                .iter_mut()
+                .filter(|r| r.matcher_for(&event).is_some())
+                .map(|r| &mut r.handler)
+                .chain(self.reactive.iter_mut().filter(|_| is_hook));
+            for handler in handlers {
+                if let Err(e) = guarded(&event, block, || handler(context)) {
+                    errors.push(e);
+                    if policy == ErrorPolicy::StopOnFirstError {
+                        break;
+                    }
+                }
+            }
+        }
+        for after in &mut self.after {
+            if let Err(e) = guarded(&event, block, || after(context, &errors)) {
+                errors.push(e);
+            }
+        }
+        // Only keep the unit's kv writes and config snapshots if the hook succeeded.  Failing
+        // to commit them fails the hook too, without hiding the handlers' errors
+        if let Err(e) = unitdata::end_hook(errors.is_empty()) {
+            errors.push(e);
+        }
+        if let Err(e) = config::end_hook(errors.is_empty()) {
+            errors.push(e);
+        }
+
+        let result = match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(JujuError::Multiple(errors)),
+        };
+        match (event, result) {
+            (Event::Action(_), Err(e)) => {
+                match super::action_fail(&e.to_string()) {
+                    Ok(_) => Err(e),
+                    // Report the handler's errors as well as action-fail's
+                    Err(fail) => {
+                        let mut errors = match e {
+                            JujuError::Multiple(errors) => errors,
+                            e => vec![e],
+                        };
+                        errors.push(fail);
+                        Err(JujuError::Multiple(errors))
+                    }
+                }
+            }
+            (_, result) => result,
+        }
+    }
+
+    /// Log a warning if `matcher` is an exact hook name that an earlier pattern also matches,
+    /// or a pattern that matches an earlier exact name.  Every matching registration runs, so
+    /// such an overlap usually means a handler runs twice by mistake.  Several exact
+    /// registrations of the same hook are deliberate and not reported.
+    fn warn_overlaps(&self, matcher: &Matcher) {
+        for earlier in self.registrations.iter().filter(|r| !r.action).flat_map(|r| &r.matchers) {
+            let name = match (earlier, matcher) {
+                (&Matcher::Exact(ref name), &Matcher::Pattern(ref pattern)) |
+                (&Matcher::Pattern(ref pattern), &Matcher::Exact(ref name))
+                    if glob_match(pattern, name) => name,
+                _ => continue,
+            };
+            super::log(format!("hook {} is handled by both {} and {}", name, earlier, matcher),
+                       Some(LogLevel::Warn));
+        }
+    }
+
+    /// Describe every registration that handles `event`, in the order they run,
+    /// ie: `["config-changed", "pattern *-changed"]`
+    pub fn matching(&self, event: &Event) -> Vec<String> {
+        self.registrations
+            .iter()
+            .filter_map(|r| r.matcher_for(event))
+            .map(|m| m.to_string())
+            .collect()
+    }
+}
+
+thread_local! {
+    /// True while this thread is running a handler under `catch_panic`
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    /// The message and backtrace of the last panic caught on this thread
+    static CAUGHT: RefCell<Option<(String, String)>> = const { RefCell::new(None) };
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+/// Run `f`, turning a panic into JujuError::Panic.  Panics on threads that are not inside
+/// `catch_panic` still go to the previously installed panic hook.
+fn catch_panic<F>(f: F) -> Result<(), JujuError>
+    where F: FnOnce() -> Result<(), JujuError>
+{
+    PANIC_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING.with(|c| c.get()) {
+                return previous(info);
+            }
+            let payload = info.payload();
+            let mut message = match payload.downcast_ref::<&str>() {
+                Some(s) => s.to_string(),
+                None => {
+                    payload.downcast_ref::<String>()
+                        .cloned()
+                        .unwrap_or_else(|| "Box<dyn Any>".to_string())
+                }
+            };
+            if let Some(location) = info.location() {
+                message = format!("{} at {}:{}", message, location.file(), location.line());
+            }
+            let backtrace = Backtrace::force_capture().to_string();
+            CAUGHT.with(|c| *c.borrow_mut() = Some((message, backtrace)));
+        }));
+    });
+
+    let was_catching = CATCHING.with(|c| c.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|c| c.set(was_catching));
+    match result {
+        Ok(result) => result,
+        Err(_) => {
+            let (message, backtrace) = CAUGHT.with(|c| c.borrow_mut().take())
+                .unwrap_or_else(|| ("unknown panic".to_string(), String::new()));
+            Err(JujuError::Panic {
+                message,
+                backtrace,
+            })
+        }
+    }
+}
+
+/// Run a handler or middleware for `event`, logging any panic and, if `block` is set,
+/// putting the unit into Blocked status
+fn guarded<F>(event: &Event, block: bool, f: F) -> Result<(), JujuError>
+    where F: FnOnce() -> Result<(), JujuError>
+{
+    let result = catch_panic(f);
+    if let Err(JujuError::Panic { ref message, ref backtrace }) = result {
+        super::log(format!("{} panicked: {}\n{}", event, message, backtrace),
+                   Some(LogLevel::Error));
+        if block {
+            let summary = message.lines().next().unwrap_or("");
+            let _ = super::status_set(Status::new(StatusType::Blocked,
+                                                  format!("{} panicked: {}", event, summary)));
+        }
+    }
+    result
+}
+
+/// A glob matcher if `name` contains a `*`, otherwise an exact one
+fn matcher(name: String) -> Matcher {
+    if name.contains('*') {
+        Matcher::Pattern(name)
+    } else {
+        Matcher::Exact(name)
+    }
+}
+
+/// Match `name` against a glob where `*` matches any run of characters
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // split always yields at least one part
+    let first = parts.next().unwrap_or("");
+    if !name.starts_with(first) {
+        return false;
+    }
+    let mut rest = &name[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        // No `*` at all, so the whole name must have matched
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.len() >= last.len() && rest.ends_with(last)
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Storage instances attached to the unit, as reported by `storage-get`.
+//!
+//! `juju::storage_list` lists the attached instances and `juju::storage_get_all` describes one.
+//! Handlers registered with `HookRegistry::on_storage_instance` receive the instance of the
+//! storage hook being run.
+//! # Examples
+//! ```
+//! extern crate juju;
+//! use juju::storage::StorageKind;
+//!
+//! fn main() {
+//!     let harness = juju::testing::Harness::new("ceph-osd/0");
+//!     harness.add_storage("osd-devices/0", "block", "/dev/vdb");
+//!
+//!     let ids = juju::storage_list(Some("osd-devices")).unwrap();
+//!     let instance = juju::storage_get_all(&ids[0]).unwrap();
+//!     assert_eq!(instance.kind, StorageKind::Block);
+//!     assert_eq!(instance.location, "/dev/vdb");
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use super::JujuError;
+use super::names::StorageId;
+
+/// Whether a storage instance is a raw device or a mounted filesystem
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StorageKind {
+    Block,
+    Filesystem,
+}
+
+impl FromStr for StorageKind {
+    type Err = JujuError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(StorageKind::Block),
+            "filesystem" => Ok(StorageKind::Filesystem),
+            _ => Err(JujuError::Parse(format!("unknown storage kind {:?}", s))),
+        }
+    }
+}
+
+impl fmt::Display for StorageKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            StorageKind::Block => "block",
+            StorageKind::Filesystem => "filesystem",
+        })
+    }
+}
+
+/// A storage instance attached to the unit
+#[derive(Clone, Debug, PartialEq)]
+pub struct StorageInstance {
+    pub id: StorageId,
+    pub kind: StorageKind,
+    /// The device path for block storage, ie: `/dev/vdb`, or the mount point for filesystem
+    /// storage
+    pub location: String,
+    /// Every other attribute storage-get reports
+    pub attributes: HashMap<String, String>,
+}
@@ -0,0 +1,808 @@
+//! An in-memory fake of the Juju model for unit testing charms.
+//!
+//! A `Harness` installs itself as the hook tool backend for the current thread and answers every
+//! wrapper in this crate from a simulated model.  Tests arrange the model, run charm code, then
+//! assert on what the charm wrote.
+//! # Examples
+//! ```
+//! extern crate juju;
+//! use juju::testing::Harness;
+//!
+//! let harness = Harness::new("wordpress/0");
+//! harness.set_config("port", 8080);
+//! harness.set_leader(true);
+//!
+//! assert_eq!(juju::config_get("port").unwrap(), Some("8080".to_string()));
+//! juju::open_port(8080, juju::Transport::Tcp).unwrap();
+//!
+//! assert!(juju::is_leader().unwrap());
+//! assert_eq!(harness.opened_ports(), vec!["8080/tcp".to_string()]);
+//! ```
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::rc::Rc;
+
+use serde::Serialize;
+use serde_json;
+use serde_json::Value;
+use super::backend::{self, HookToolBackend, HookToolOutput};
+use super::JujuError;
+use super::names::RelationId;
+use super::network::{BindAddress, InterfaceAddress, NetworkInfo};
+
+/// The data bags and membership of a single simulated relation
+#[derive(Clone, Debug, Default)]
+struct RelationState {
+    /// Remote units that have joined the relation, in join order
+    units: Vec<String>,
+    /// Data bags keyed by unit name
+    data: HashMap<String, HashMap<String, String>>,
+    /// Application data bags keyed by application name
+    app_data: HashMap<String, HashMap<String, String>>,
+}
+
+/// The simulated model a Harness answers hook tool calls from
+#[derive(Debug, Default)]
+struct Model {
+    unit_name: String,
+    private_address: String,
+    public_address: String,
+    /// network-get answers keyed by binding.  Other bindings answer with the private address
+    networks: BTreeMap<String, NetworkInfo>,
+    config: BTreeMap<String, Value>,
+    relations: BTreeMap<String, RelationState>,
+    next_relation_id: usize,
+    /// The relation id and remote unit of the relation hook being simulated
+    hook_relation: Option<(String, Option<String>)>,
+    /// The storage instance of the storage hook being simulated
+    hook_storage: Option<String>,
+    /// Other JUJU_* variables of the hook being simulated, ie: JUJU_ACTION_NAME
+    vars: BTreeMap<String, String>,
+    leader: bool,
+    leader_settings: BTreeMap<String, String>,
+    status: Option<(String, String)>,
+    /// The `status-data` status-get reports for the unit.  Cleared by status-set
+    status_data: BTreeMap<String, String>,
+    application_status: Option<(String, String)>,
+    opened_ports: Vec<String>,
+    storage: BTreeMap<String, BTreeMap<String, String>>,
+    /// Instances requested with storage-add, keyed by storage name
+    storage_added: BTreeMap<String, usize>,
+    action_params: BTreeMap<String, String>,
+    action_results: BTreeMap<String, String>,
+    action_failure: Option<String>,
+    metrics: BTreeMap<String, String>,
+    application_version: Option<String>,
+    reboot_requested: bool,
+    logs: Vec<String>,
+}
+
+/// Hook tool arguments split into flags and positional arguments
+struct Args {
+    flags: HashMap<String, String>,
+    positional: Vec<String>,
+}
+
+// Flags which consume the following argument as their value
+const VALUE_FLAGS: &[&str] = &["r", "relation", "s", "l", "log-level", "format", "file"];
+
+impl Args {
+    fn parse(args: &[String]) -> Args {
+        let mut flags: HashMap<String, String> = HashMap::new();
+        let mut positional: Vec<String> = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "-" || !arg.starts_with('-') {
+                positional.push(arg.clone());
+                continue;
+            }
+            let name = arg.trim_start_matches('-');
+            if let Some(position) = name.find('=') {
+                flags.insert(name[..position].to_string(), name[position + 1..].to_string());
+            } else if VALUE_FLAGS.contains(&name) {
+                flags.insert(name.to_string(),
+                             iter.next().cloned().unwrap_or_default());
+            } else if !arg.starts_with("--") && name.len() > 1 &&
+                      VALUE_FLAGS.contains(&&name[..1]) {
+                // Short flag with its value attached, ie: -rdb:1
+                flags.insert(name[..1].to_string(), name[1..].trim().to_string());
+            } else {
+                flags.insert(name.to_string(), String::new());
+            }
+        }
+        Args { flags, positional }
+    }
+
+    fn flag(&self, name: &str) -> Option<&String> {
+        self.flags.get(name)
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.flags.contains_key(name)
+    }
+}
+
+/// Render a single value the way hook tools print it by default
+fn render_value(value: &Value) -> String {
+    match *value {
+        Value::String(ref s) => s.clone(),
+        Value::Null => String::new(),
+        ref other => other.to_string(),
+    }
+}
+
+/// Render a map as `key: value` lines the way hook tools print it by default
+fn render_map<'a, I>(entries: I) -> String
+    where I: Iterator<Item = (&'a String, String)>
+{
+    let mut sorted: Vec<(&String, String)> = entries.collect();
+    sorted.sort();
+    sorted.iter().map(|(k, v)| format!("{}: {}\n", k, v)).collect()
+}
+
+/// Split `key=value` arguments into a map
+fn parse_settings(args: &[String]) -> Result<Vec<(String, String)>, String> {
+    let mut settings = Vec::new();
+    for arg in args {
+        match arg.find('=') {
+            Some(position) => {
+                settings.push((arg[..position].to_string(), arg[position + 1..].to_string()))
+            }
+            None => return Err(format!("expected \"key=value\", got {:?}", arg)),
+        }
+    }
+    Ok(settings)
+}
+
+/// Read the settings in a relation-set `--file`.  Only JSON, the subset of YAML this crate
+/// writes, is understood.
+fn read_settings_file(path: &str) -> Result<Vec<(String, String)>, String> {
+    let mut s = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut s)).map_err(|e| e.to_string())?;
+    let settings: BTreeMap<String, String> = serde_json::from_str(&s)
+        .map_err(|e| format!("cannot parse {}: {}", path, e))?;
+    Ok(settings.into_iter().collect())
+}
+
+impl Model {
+    /// The application this unit belongs to, ie: `wordpress` for `wordpress/0`
+    fn app_name(&self) -> &str {
+        self.unit_name.split('/').next().unwrap_or("")
+    }
+
+    /// Resolve the relation a relation tool is operating on
+    fn relation_id(&self, args: &Args) -> Result<String, String> {
+        match args.flag("r").or(args.flag("relation")) {
+            Some(id) => {
+                if self.relations.contains_key(id) {
+                    Ok(id.clone())
+                } else {
+                    Err(format!("invalid value {:?} for option -r: relation not found", id))
+                }
+            }
+            None => {
+                match self.hook_relation {
+                    Some((ref id, _)) => Ok(id.clone()),
+                    None => Err("no relation id specified".to_string()),
+                }
+            }
+        }
+    }
+
+    fn run(&mut self, tool: &str, args: &[String]) -> Result<String, String> {
+        let parsed = Args::parse(args);
+        match tool {
+            "action-get" => {
+                match parsed.positional.first() {
+                    Some(key) => Ok(self.action_params.get(key).cloned().unwrap_or_default()),
+                    None => {
+                        Ok(render_map(self.action_params.iter().map(|(k, v)| (k, v.clone()))))
+                    }
+                }
+            }
+            "action-set" => {
+                for (k, v) in parse_settings(&parsed.positional)? {
+                    self.action_results.insert(k, v);
+                }
+                Ok(String::new())
+            }
+            "action-fail" => {
+                self.action_failure = Some(parsed.positional.join(" "));
+                Ok(String::new())
+            }
+            "add-metric" => {
+                for (k, v) in parse_settings(&parsed.positional)? {
+                    self.metrics.insert(k, v);
+                }
+                Ok(String::new())
+            }
+            "application-version-set" => {
+                self.application_version = parsed.positional.first().cloned();
+                Ok(String::new())
+            }
+            "unit-get" => {
+                match parsed.positional.first().map(|s| s.as_str()) {
+                    Some("private-address") => Ok(self.private_address.clone()),
+                    Some("public-address") => Ok(self.public_address.clone()),
+                    other => Err(format!("unknown setting {:?}", other)),
+                }
+            }
+            "network-get" => {
+                let binding = match parsed.positional.first() {
+                    Some(binding) => binding,
+                    None => return Err("no endpoint binding specified".to_string()),
+                };
+                let info = match self.networks.get(binding) {
+                    Some(info) => info.clone(),
+                    None => {
+                        let address = InterfaceAddress {
+                            address: self.private_address.clone(),
+                            ..Default::default()
+                        };
+                        NetworkInfo {
+                            bind_addresses: vec![BindAddress {
+                                                     interface_name: "eth0".to_string(),
+                                                     addresses: vec![address],
+                                                     ..Default::default()
+                                                 }],
+                            ingress_addresses: vec![self.private_address.clone()],
+                            egress_subnets: vec![format!("{}/32", self.private_address)],
+                        }
+                    }
+                };
+                Ok(serde_json::to_string(&info).map_err(|e| e.to_string())?)
+            }
+            "config-get" => {
+                let json = parsed.flag("format").map(|f| f.as_str()) == Some("json");
+                if parsed.has("all") || parsed.positional.is_empty() {
+                    if json {
+                        Ok(serde_json::to_string(&self.config).map_err(|e| e.to_string())?)
+                    } else {
+                        Ok(render_map(self.config.iter().map(|(k, v)| (k, render_value(v)))))
+                    }
+                } else {
+                    let value = self.config.get(&parsed.positional[0]);
+                    if json {
+                        Ok(serde_json::to_string(&value).map_err(|e| e.to_string())?)
+                    } else {
+                        Ok(value.map(render_value).unwrap_or_default())
+                    }
+                }
+            }
+            "open-port" => {
+                let port = parsed.positional.join("");
+                if !self.opened_ports.contains(&port) {
+                    self.opened_ports.push(port);
+                }
+                Ok(String::new())
+            }
+            "close-port" => {
+                let port = parsed.positional.join("");
+                self.opened_ports.retain(|p| p != &port);
+                Ok(String::new())
+            }
+            "relation-ids" => {
+                // Like Juju, only a relation hook may leave out the endpoint
+                let endpoint = match (parsed.positional.first(), self.hook_relation.as_ref()) {
+                    (Some(endpoint), _) => endpoint.clone(),
+                    (None, Some((id, _))) => id.split(':').next().unwrap_or("").to_string(),
+                    (None, None) => return Err("no endpoint name specified".to_string()),
+                };
+                let ids: Vec<&String> = self.relations
+                    .keys()
+                    .filter(|id| id.split(':').next() == Some(endpoint.as_str()))
+                    .collect();
+                Ok(ids.iter().map(|id| format!("{}\n", id)).collect())
+            }
+            "relation-list" => {
+                let id = self.relation_id(&parsed)?;
+                Ok(self.relations[&id].units.iter().map(|u| format!("{}\n", u)).collect())
+            }
+            "relation-get" => {
+                let id = self.relation_id(&parsed)?;
+                let key = parsed.positional.first().cloned().unwrap_or("-".to_string());
+                let unit = match parsed.positional.get(1) {
+                    Some(unit) => unit.clone(),
+                    None => {
+                        match self.hook_relation {
+                            Some((_, Some(ref remote))) => remote.clone(),
+                            _ => return Err("no unit id specified".to_string()),
+                        }
+                    }
+                };
+                let empty = HashMap::new();
+                let bag = if parsed.has("app") {
+                    // The target names an application, or a unit of that application
+                    let app = unit.split('/').next().unwrap_or("").to_string();
+                    if app == self.app_name() && !self.leader {
+                        return Err("permission denied: only the leader can read its own \
+                                    application data bag"
+                            .to_string());
+                    }
+                    self.relations[&id].app_data.get(&app).unwrap_or(&empty)
+                } else {
+                    self.relations[&id].data.get(&unit).unwrap_or(&empty)
+                };
+                let json = parsed.flag("format").map(|f| f.as_str()) == Some("json");
+                match (key == "-", json) {
+                    (true, true) => Ok(serde_json::to_string(bag).map_err(|e| e.to_string())?),
+                    (true, false) => Ok(render_map(bag.iter().map(|(k, v)| (k, v.clone())))),
+                    (false, true) => {
+                        Ok(serde_json::to_string(&bag.get(&key)).map_err(|e| e.to_string())?)
+                    }
+                    (false, false) => Ok(bag.get(&key).cloned().unwrap_or_default()),
+                }
+            }
+            "relation-set" => {
+                let id = self.relation_id(&parsed)?;
+                // Settings from --file are applied first, then those on the command line
+                let mut settings = match parsed.flag("file") {
+                    Some(path) => read_settings_file(path)?,
+                    None => Vec::new(),
+                };
+                settings.extend(parse_settings(&parsed.positional)?);
+                let relation = self.relations
+                    .get_mut(&id)
+                    .expect("relation_id only returns known relations");
+                let bag = if parsed.has("app") {
+                    if !self.leader {
+                        return Err("permission denied: only the leader can write the \
+                                    application data bag"
+                            .to_string());
+                    }
+                    let app = self.unit_name.split('/').next().unwrap_or("");
+                    relation.app_data.entry(app.to_string()).or_default()
+                } else {
+                    relation.data.entry(self.unit_name.clone()).or_default()
+                };
+                for (k, v) in settings {
+                    if v.is_empty() {
+                        bag.remove(&k);
+                    } else {
+                        bag.insert(k, v);
+                    }
+                }
+                Ok(String::new())
+            }
+            "status-set" => {
+                let status_type = parsed.positional.first().cloned().unwrap_or_default();
+                let message = parsed.positional.get(1).cloned().unwrap_or_default();
+                if parsed.has("application") {
+                    if !self.leader {
+                        return Err("cannot set application status: not the leader".to_string());
+                    }
+                    self.application_status = Some((status_type, message));
+                } else {
+                    self.status = Some((status_type, message));
+                    self.status_data.clear();
+                }
+                Ok(String::new())
+            }
+            "status-get" => {
+                let application = parsed.has("application");
+                if application && !self.leader {
+                    return Err("cannot read application status: not the leader".to_string());
+                }
+                let status = if application { &self.application_status } else { &self.status };
+                let (status_type, message) = match *status {
+                    Some((ref status_type, ref message)) => (status_type.clone(), message.clone()),
+                    None => ("unknown".to_string(), String::new()),
+                };
+                if parsed.flag("format").map(|f| f.as_str()) != Some("json") {
+                    return Ok(status_type);
+                }
+                let mut map = serde_json::Map::new();
+                map.insert("status".to_string(), Value::String(status_type));
+                map.insert("message".to_string(), Value::String(message));
+                if parsed.has("include-data") {
+                    let data = if application { BTreeMap::new() } else { self.status_data.clone() };
+                    map.insert("status-data".to_string(),
+                               serde_json::to_value(data).map_err(|e| e.to_string())?);
+                }
+                let value = if application {
+                    let mut outer = serde_json::Map::new();
+                    outer.insert("application-status".to_string(), Value::Object(map));
+                    Value::Object(outer)
+                } else {
+                    Value::Object(map)
+                };
+                Ok(value.to_string())
+            }
+            "storage-get" => {
+                let id = match parsed.flag("s").or(self.hook_storage.as_ref()) {
+                    Some(id) => id.clone(),
+                    None => return Err("no storage instance specified".to_string()),
+                };
+                let attributes = match self.storage.get(&id) {
+                    Some(attributes) => attributes,
+                    None => return Err(format!("storage instance {:?} not found", id)),
+                };
+                let json = parsed.flag("format").map(|f| f.as_str()) == Some("json");
+                match (parsed.positional.first(), json) {
+                    (Some(key), true) => {
+                        Ok(serde_json::to_string(&attributes.get(key)).map_err(|e| e.to_string())?)
+                    }
+                    (Some(key), false) => Ok(attributes.get(key).cloned().unwrap_or_default()),
+                    (None, true) => {
+                        Ok(serde_json::to_string(attributes).map_err(|e| e.to_string())?)
+                    }
+                    (None, false) => {
+                        Ok(render_map(attributes.iter().map(|(k, v)| (k, v.clone()))))
+                    }
+                }
+            }
+            "storage-list" => {
+                let name = parsed.positional.first();
+                Ok(self.storage
+                    .keys()
+                    .filter(|id| match name {
+                        Some(name) => id.split('/').next() == Some(name.as_str()),
+                        None => true,
+                    })
+                    .map(|id| format!("{}\n", id))
+                    .collect())
+            }
+            "storage-add" => {
+                for arg in &parsed.positional {
+                    let (name, count) = match arg.find('=') {
+                        Some(position) => (&arg[..position], &arg[position + 1..]),
+                        None => (arg.as_str(), "1"),
+                    };
+                    let count: usize = count.parse()
+                        .map_err(|_| format!("invalid storage count {:?}", count))?;
+                    *self.storage_added.entry(name.to_string()).or_insert(0) += count;
+                }
+                Ok(String::new())
+            }
+            "leader-get" => {
+                match parsed.positional.first().map(|s| s.as_str()) {
+                    None | Some("-") => {
+                        Ok(render_map(self.leader_settings.iter().map(|(k, v)| (k, v.clone()))))
+                    }
+                    Some(key) => Ok(self.leader_settings.get(key).cloned().unwrap_or_default()),
+                }
+            }
+            "leader-set" => {
+                if !self.leader {
+                    return Err("cannot write leadership settings: not the leader".to_string());
+                }
+                for (k, v) in parse_settings(&parsed.positional)? {
+                    if v.is_empty() {
+                        self.leader_settings.remove(&k);
+                    } else {
+                        self.leader_settings.insert(k, v);
+                    }
+                }
+                Ok(String::new())
+            }
+            "is-leader" => Ok(if self.leader { "True" } else { "False" }.to_string()),
+            "juju-log" => {
+                let message = parsed.positional.join(" ");
+                match parsed.flag("l").or(parsed.flag("log-level")) {
+                    Some(level) => self.logs.push(format!("{}: {}", level, message)),
+                    None => self.logs.push(message),
+                }
+                Ok(String::new())
+            }
+            "sudo" if args.first().map(|s| s.as_str()) == Some("juju-reboot") => {
+                self.reboot_requested = true;
+                Ok(String::new())
+            }
+            _ => Err(format!("{}: command not found", tool)),
+        }
+    }
+}
+
+/// The hook tool backend a Harness installs.  Answers every call from the shared model
+struct ModelBackend {
+    model: Rc<RefCell<Model>>,
+}
+
+impl HookToolBackend for ModelBackend {
+    fn invoke(&self, tool: &str, args: &[String]) -> Result<HookToolOutput, JujuError> {
+        match self.model.borrow_mut().run(tool, args) {
+            Ok(stdout) => Ok(HookToolOutput::from_stdout(stdout)),
+            Err(stderr) => Ok(HookToolOutput::from_failure(1, format!("ERROR {}\n", stderr))),
+        }
+    }
+
+    /// JUJU_* variables are answered from the model only, so tests don't depend on the
+    /// environment they run in.  Anything else, ie: CHARM_DIR, comes from the process
+    fn var(&self, name: &str) -> Result<String, env::VarError> {
+        let model = self.model.borrow();
+        let value = match name {
+            "JUJU_UNIT_NAME" => Some(model.unit_name.clone()),
+            "JUJU_RELATION" => {
+                model.hook_relation
+                    .as_ref()
+                    .map(|(id, _)| id.split(':').next().unwrap_or("").to_string())
+            }
+            "JUJU_RELATION_ID" => model.hook_relation.as_ref().map(|(id, _)| id.clone()),
+            "JUJU_REMOTE_UNIT" => model.hook_relation.as_ref().and_then(|(_, unit)| unit.clone()),
+            "JUJU_STORAGE_ID" => model.hook_storage.clone(),
+            _ if name.starts_with("JUJU_") => model.vars.get(name).cloned(),
+            _ => return env::var(name),
+        };
+        value.ok_or(env::VarError::NotPresent)
+    }
+}
+
+/// A simulated Juju model that answers the hook tools for the current thread.
+/// The previous hook tool backend is restored when the Harness is dropped.
+pub struct Harness {
+    model: Rc<RefCell<Model>>,
+    previous: Option<Rc<dyn HookToolBackend>>,
+}
+
+impl Harness {
+    /// Create a Harness simulating the given unit, ie: `wordpress/0`, and install it as
+    /// the hook tool backend for the current thread.
+    pub fn new(unit_name: &str) -> Harness {
+        let model = Rc::new(RefCell::new(Model {
+            unit_name: unit_name.to_string(),
+            private_address: "10.0.0.10".to_string(),
+            public_address: "10.0.0.10".to_string(),
+            ..Default::default()
+        }));
+        let previous = backend::set_backend(Rc::new(ModelBackend { model: model.clone() }));
+        Harness { model, previous }
+    }
+
+    /// Set the unit's private and public addresses
+    pub fn set_addresses(&self, private_address: &str, public_address: &str) {
+        let mut model = self.model.borrow_mut();
+        model.private_address = private_address.to_string();
+        model.public_address = public_address.to_string();
+    }
+
+    /// Set what network-get reports for an endpoint binding.  Bindings without one report
+    /// the private address on `eth0`.
+    pub fn set_network(&self, binding: &str, info: NetworkInfo) {
+        self.model.borrow_mut().networks.insert(binding.to_string(), info);
+    }
+
+    /// Set a config option.  The value can be anything Juju config supports: a string, an
+    /// integer, a float or a boolean.
+    pub fn set_config<T: Serialize>(&self, key: &str, value: T) {
+        let value = serde_json::to_value(value).expect("config values must serialize to JSON");
+        self.model.borrow_mut().config.insert(key.to_string(), value);
+    }
+
+    /// Remove a config option, as if it had no value and no default
+    pub fn unset_config(&self, key: &str) {
+        self.model.borrow_mut().config.remove(key);
+    }
+
+    /// Set whether this unit is the leader
+    pub fn set_leader(&self, leader: bool) {
+        self.model.borrow_mut().leader = leader;
+    }
+
+    /// Set a leader setting, as if another unit had written it
+    pub fn set_leader_setting(&self, key: &str, value: &str) {
+        self.model.borrow_mut().leader_settings.insert(key.to_string(), value.to_string());
+    }
+
+    /// Returns the current leader settings
+    pub fn leader_settings(&self) -> HashMap<String, String> {
+        self.model.borrow().leader_settings.clone().into_iter().collect()
+    }
+
+    /// Add a relation on the given endpoint, ie: `db`.  Returns the new relation id, ie: `db:0`.
+    /// # Panics
+    /// Panics if `endpoint` is not a valid endpoint name
+    pub fn add_relation(&self, endpoint: &str) -> RelationId {
+        let mut model = self.model.borrow_mut();
+        let id = RelationId::new(endpoint, model.next_relation_id).expect("invalid endpoint name");
+        model.next_relation_id += 1;
+        model.relations.insert(id.to_string(), RelationState::default());
+        id
+    }
+
+    /// Add a remote unit, ie: `mysql/0`, to a relation and set its data bag
+    pub fn add_relation_unit(&self,
+                             relation_id: &RelationId,
+                             unit: &str,
+                             data: HashMap<String, String>) {
+        let mut model = self.model.borrow_mut();
+        let relation = model.relations
+            .get_mut(&relation_id.to_string())
+            .expect("unknown relation id");
+        if !relation.units.iter().any(|u| u == unit) {
+            relation.units.push(unit.to_string());
+        }
+        relation.data.insert(unit.to_string(), data);
+    }
+
+    /// Remove a remote unit from a relation, as if it had departed
+    pub fn remove_relation_unit(&self, relation_id: &RelationId, unit: &str) {
+        let mut model = self.model.borrow_mut();
+        let relation = model.relations
+            .get_mut(&relation_id.to_string())
+            .expect("unknown relation id");
+        relation.units.retain(|u| u != unit);
+        relation.data.remove(unit);
+    }
+
+    /// Returns the data bag a unit has on a relation.  Pass this unit's own name to see what
+    /// the charm wrote with relation-set.
+    pub fn relation_data(&self, relation_id: &RelationId, unit: &str) -> HashMap<String, String> {
+        let model = self.model.borrow();
+        model.relations
+            .get(&relation_id.to_string())
+            .and_then(|r| r.data.get(unit))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Set the application data bag of an application, ie: `mysql`, on a relation
+    pub fn set_relation_app_data(&self,
+                                 relation_id: &RelationId,
+                                 app: &str,
+                                 data: HashMap<String, String>) {
+        let mut model = self.model.borrow_mut();
+        let relation = model.relations
+            .get_mut(&relation_id.to_string())
+            .expect("unknown relation id");
+        relation.app_data.insert(app.to_string(), data);
+    }
+
+    /// Returns the application data bag an application has on a relation.  Pass this unit's
+    /// application name to see what the charm wrote with relation-set --app.
+    pub fn relation_app_data(&self,
+                             relation_id: &RelationId,
+                             app: &str)
+                             -> HashMap<String, String> {
+        let model = self.model.borrow();
+        model.relations
+            .get(&relation_id.to_string())
+            .and_then(|r| r.app_data.get(app))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Simulate running inside a relation hook for the given relation and remote unit.
+    /// Relation tools called without an explicit relation id will use this relation.
+    pub fn set_hook_relation(&self, relation_id: &RelationId, remote_unit: Option<&str>) {
+        self.model.borrow_mut().hook_relation = Some((relation_id.to_string(),
+                                                      remote_unit.map(|u| u.to_string())));
+    }
+
+    /// Stop simulating a relation hook
+    pub fn clear_hook_relation(&self) {
+        self.model.borrow_mut().hook_relation = None;
+    }
+
+    /// Simulate running inside a storage hook for the given storage instance, ie: `data/0`.
+    /// storage-get called without `-s` describes this instance.
+    pub fn set_hook_storage(&self, id: Option<&str>) {
+        self.model.borrow_mut().hook_storage = id.map(|id| id.to_string());
+    }
+
+    /// Simulate running the named action, setting JUJU_ACTION_NAME, JUJU_ACTION_UUID and
+    /// JUJU_ACTION_TAG
+    pub fn set_action(&self, name: &str, uuid: &str) {
+        self.set_var("JUJU_ACTION_NAME", name);
+        self.set_var("JUJU_ACTION_UUID", uuid);
+        self.set_var("JUJU_ACTION_TAG", &format!("action-{}", uuid));
+    }
+
+    /// Simulate a meter-status-changed hook, setting JUJU_METER_STATUS and JUJU_METER_INFO
+    pub fn set_meter_status(&self, status: &str, info: &str) {
+        self.set_var("JUJU_METER_STATUS", status);
+        self.set_var("JUJU_METER_INFO", info);
+    }
+
+    /// Set JUJU_AVAILABILITY_ZONE
+    pub fn set_availability_zone(&self, zone: &str) {
+        self.set_var("JUJU_AVAILABILITY_ZONE", zone);
+    }
+
+    /// Set any other JUJU_* variable of the simulated hook.  An empty value unsets it.  The
+    /// unit, relation and storage variables follow `Harness::new`, `set_hook_relation` and
+    /// `set_hook_storage` instead.
+    pub fn set_var(&self, name: &str, value: &str) {
+        let mut model = self.model.borrow_mut();
+        if value.is_empty() {
+            model.vars.remove(name);
+        } else {
+            model.vars.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    /// Returns the last status set with status-set as a (status type, message) tuple
+    pub fn status(&self) -> Option<(String, String)> {
+        self.model.borrow().status.clone()
+    }
+
+    /// Set the unit status as if Juju had set it, including the `status-data` that
+    /// status-get reports with it, ie: the failed hook of an error status
+    pub fn set_status(&self, status_type: &str, message: &str, data: HashMap<String, String>) {
+        let mut model = self.model.borrow_mut();
+        model.status = Some((status_type.to_string(), message.to_string()));
+        model.status_data = data.into_iter().collect();
+    }
+
+    /// Returns the last application status set with `status-set --application` as a
+    /// (status type, message) tuple
+    pub fn application_status(&self) -> Option<(String, String)> {
+        self.model.borrow().application_status.clone()
+    }
+
+    /// Returns the ports that are currently open, ie: `80/tcp`
+    pub fn opened_ports(&self) -> Vec<String> {
+        self.model.borrow().opened_ports.clone()
+    }
+
+    /// Attach a storage instance, ie: `data/0`, mounted at the given location
+    pub fn add_storage(&self, id: &str, kind: &str, location: &str) {
+        let mut attributes = BTreeMap::new();
+        attributes.insert("kind".to_string(), kind.to_string());
+        attributes.insert("location".to_string(), location.to_string());
+        self.model.borrow_mut().storage.insert(id.to_string(), attributes);
+    }
+
+    /// Detach a storage instance
+    pub fn remove_storage(&self, id: &str) {
+        self.model.borrow_mut().storage.remove(id);
+    }
+
+    /// How many instances of each storage were requested with storage-add.  Nothing is
+    /// attached; use `add_storage` to simulate the instances arriving.
+    pub fn storage_added(&self) -> BTreeMap<String, usize> {
+        self.model.borrow().storage_added.clone()
+    }
+
+    /// Set the parameters of the action being simulated
+    pub fn set_action_params(&self, params: HashMap<String, String>) {
+        self.model.borrow_mut().action_params = params.into_iter().collect();
+    }
+
+    /// Returns the results the charm set with action-set
+    pub fn action_results(&self) -> HashMap<String, String> {
+        self.model.borrow().action_results.clone().into_iter().collect()
+    }
+
+    /// Returns the message passed to action-fail, if the action failed
+    pub fn action_failure(&self) -> Option<String> {
+        self.model.borrow().action_failure.clone()
+    }
+
+    /// Returns the metrics added with add-metric
+    pub fn metrics(&self) -> HashMap<String, String> {
+        self.model.borrow().metrics.clone().into_iter().collect()
+    }
+
+    /// Returns the version set with application-version-set
+    pub fn application_version(&self) -> Option<String> {
+        self.model.borrow().application_version.clone()
+    }
+
+    /// Returns true if the charm asked for the machine to be rebooted
+    pub fn reboot_requested(&self) -> bool {
+        self.model.borrow().reboot_requested
+    }
+
+    /// Returns every message written with juju-log.  Messages logged with a level are
+    /// prefixed with it, ie: `WARN: disk is almost full`
+    pub fn logs(&self) -> Vec<String> {
+        self.model.borrow().logs.clone()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        match self.previous.take() {
+            Some(previous) => {
+                backend::set_backend(previous);
+            }
+            None => {
+                backend::reset_backend();
+            }
+        }
+    }
+}
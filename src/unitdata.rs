@@ -3,9 +3,11 @@ extern crate rusqlite;
 extern crate serde;
 extern crate serde_json;
 
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::env;
 use std::iter;
+use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
 
 use self::chrono::{DateTime, Utc};
@@ -13,7 +15,8 @@ use self::rusqlite::Connection;
 use self::rusqlite::Error::QueryReturnedNoRows;
 use self::serde::{Deserialize, Serialize};
 use self::serde_json::Value;
-use super::JujuError;
+use super::{config_get_all, JujuError};
+use super::names::{RelationId, UnitName};
 
 #[derive(Debug)]
 /// A connection to the unit's Key/Value data
@@ -22,6 +25,8 @@ use super::JujuError;
 pub struct Storage {
     conn: Connection,
     revision: Option<u32>,
+    /// How many `transaction` savepoints are open
+    savepoints: usize,
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,12 +35,137 @@ pub struct Record {
 }
 
 #[derive(Debug)]
+/// A value a key held after a hook scoped revision
 pub struct History {
-    data: Value,
-    date: DateTime<Utc>,
-    hook: String,
-    key: String,
-    revision: u32,
+    pub data: Value,
+    pub date: DateTime<Utc>,
+    pub hook: String,
+    pub key: String,
+    pub revision: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// How a key's value differs from what was stored.  A value of None means the key
+/// was not stored before, or has been removed now.
+pub struct Delta {
+    pub previous: Option<Value>,
+    pub current: Option<Value>,
+}
+
+#[derive(Debug)]
+/// A guard returned by `Storage::hook_scope`.  Every write made through it is recorded
+/// under the hook's revision.  The revision is cleared when the guard is dropped.
+pub struct HookScope<'a> {
+    storage: &'a mut Storage,
+}
+
+impl<'a> HookScope<'a> {
+    /// The revision writes in this scope are recorded under
+    pub fn revision(&self) -> u32 {
+        self.storage.revision.unwrap_or(0)
+    }
+}
+
+impl<'a> Deref for HookScope<'a> {
+    type Target = Storage;
+
+    fn deref(&self) -> &Storage {
+        self.storage
+    }
+}
+
+impl<'a> DerefMut for HookScope<'a> {
+    fn deref_mut(&mut self) -> &mut Storage {
+        self.storage
+    }
+}
+
+impl<'a> Drop for HookScope<'a> {
+    fn drop(&mut self) {
+        self.storage.revision = None;
+    }
+}
+
+thread_local! {
+    static KV: RefCell<Option<Storage>> = const { RefCell::new(None) };
+    static IN_HOOK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run a closure with the unit's shared Storage, connecting to the default database the
+/// first time it is used.  Inside `process_hooks` everything written through it is part
+/// of a single transaction that is committed only if the hook succeeds.
+/// # Examples
+/// ```
+/// extern crate juju;
+/// use juju::unitdata::{self, Storage};
+///
+/// let db = std::env::temp_dir().join("with_kv_example.db");
+/// unitdata::set_kv(Some(Storage::new(Some(db)).unwrap()));
+///
+/// unitdata::with_kv(|kv| kv.set("installed", true)).unwrap();
+/// let installed: Option<bool> = unitdata::with_kv(|kv| kv.get("installed")).unwrap();
+/// assert_eq!(installed, Some(true));
+/// ```
+/// # Failures
+/// Returns a JujuError if the database cannot be opened or `with_kv` is called from inside
+/// another `with_kv` closure
+pub fn with_kv<F, T>(f: F) -> Result<T, JujuError>
+    where F: FnOnce(&mut Storage) -> Result<T, JujuError>
+{
+    KV.with(|kv| {
+        let mut kv = kv.try_borrow_mut()
+            .map_err(|_| {
+                JujuError::InvalidState("with_kv cannot be called recursively".to_string())
+            })?;
+        if kv.is_none() {
+            let storage = Storage::new(None)?;
+            if IN_HOOK.with(|h| h.get()) {
+                storage.begin()?;
+            }
+            *kv = Some(storage);
+        }
+        f(kv.as_mut().expect("the shared Storage was opened above"))
+    })
+}
+
+/// Replace the shared Storage used by `with_kv` on this thread, ie: with a database at a
+/// different path.  Passing None closes it so the next `with_kv` reconnects to the
+/// default database.  Returns the previous Storage.
+pub fn set_kv(storage: Option<Storage>) -> Option<Storage> {
+    KV.with(|kv| {
+        let storage = storage.inspect(|s| {
+            if IN_HOOK.with(|h| h.get()) && !s.in_transaction() {
+                let _ = s.begin();
+            }
+        });
+        std::mem::replace(&mut *kv.borrow_mut(), storage)
+    })
+}
+
+/// Called when a hook starts.  Writes made through `with_kv` until `end_hook` are
+/// grouped into a single transaction.
+pub fn begin_hook() -> Result<(), JujuError> {
+    IN_HOOK.with(|h| h.set(true));
+    KV.with(|kv| match *kv.borrow() {
+        Some(ref storage) if !storage.in_transaction() => storage.begin(),
+        _ => Ok(()),
+    })
+}
+
+/// Called when a hook finishes.  Commits the hook's writes if it succeeded and rolls
+/// them back otherwise.
+pub fn end_hook(success: bool) -> Result<(), JujuError> {
+    IN_HOOK.with(|h| h.set(false));
+    KV.with(|kv| match *kv.borrow() {
+        Some(ref storage) if storage.in_transaction() => {
+            if success {
+                storage.commit()
+            } else {
+                storage.rollback()
+            }
+        }
+        _ => Ok(()),
+    })
 }
 
 impl Storage {
@@ -44,8 +174,10 @@ impl Storage {
         let db_path = match path {
             Some(p) => p,
             None => {
-                PathBuf::from(env::var("UNIT_STATE_DB")
-                    .unwrap_or(format!("{}.unit-state.db", env::var("CHARM_DIR").unwrap())))
+                match env::var("UNIT_STATE_DB") {
+                    Ok(p) => PathBuf::from(p),
+                    Err(_) => PathBuf::from(format!("{}.unit-state.db", env::var("CHARM_DIR")?)),
+                }
             }
         };
 
@@ -53,6 +185,7 @@ impl Storage {
         let storage = Storage {
             conn: conn,
             revision: None,
+            savepoints: 0,
         };
         storage.init()?;
         Ok(storage)
@@ -73,6 +206,66 @@ impl Storage {
         Ok(())
     }
 
+    /// Start a transaction.  Nothing written after this is saved until `commit` is called.
+    pub fn begin(&self) -> Result<(), JujuError> {
+        self.conn.execute_batch("BEGIN")?;
+        Ok(())
+    }
+
+    /// Save everything written since `begin`
+    pub fn commit(&self) -> Result<(), JujuError> {
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    /// Throw away everything written since `begin`
+    pub fn rollback(&self) -> Result<(), JujuError> {
+        self.conn.execute_batch("ROLLBACK")?;
+        Ok(())
+    }
+
+    /// Returns true if a transaction has been started and not yet committed or rolled back
+    pub fn in_transaction(&self) -> bool {
+        !self.conn.is_autocommit()
+    }
+
+    /// Run a closure inside a transaction.  The transaction is committed if the closure
+    /// returns Ok and rolled back if it returns an Err.
+    /// Inside another transaction, ie: the one a dispatched hook runs in, a savepoint is used
+    /// instead, so an Err only rolls back what the closure wrote.
+    pub fn transaction<F, T>(&mut self, f: F) -> Result<T, JujuError>
+        where F: FnOnce(&mut Storage) -> Result<T, JujuError>
+    {
+        if self.in_transaction() {
+            let name = format!("unitdata_{}", self.savepoints + 1);
+            self.conn.execute_batch(&format!("SAVEPOINT {}", name))?;
+            self.savepoints += 1;
+            let result = f(self);
+            self.savepoints -= 1;
+            return match result {
+                Ok(value) => {
+                    self.conn.execute_batch(&format!("RELEASE {}", name))?;
+                    Ok(value)
+                }
+                Err(e) => {
+                    self.conn.execute_batch(&format!("ROLLBACK TO {0}; RELEASE {0}", name))?;
+                    Err(e)
+                }
+            };
+        }
+        self.begin()?;
+        match f(self) {
+            Ok(value) => {
+                self.commit()?;
+                Ok(value)
+            }
+            Err(e) => {
+                self.rollback()?;
+                Err(e)
+            }
+        }
+    }
+
     pub fn get<T>(&self, key: &str) -> Result<Option<T>, JujuError>
         where T: Deserialize
     {
@@ -101,8 +294,10 @@ impl Storage {
                     -> Result<HashMap<String, Value>, JujuError> {
         let mut results: HashMap<String, Value> = HashMap::new();
         let mut stmt = self.conn
-            .prepare("select key, data from kv where key like ?")?;
-        let mut rows = stmt.query(&[&format!("{}%", key_prefix)])?;
+            .prepare("select key, data from kv where substr(key, 1, ?) = ?")?;
+        // Compared literally rather than with LIKE, which treats `_` and `%` as wildcards
+        // and ignores case
+        let mut rows = stmt.query(&[&(key_prefix.chars().count() as i64), &key_prefix])?;
 
         while let Some(result_row) = rows.next() {
             let row = result_row?;
@@ -110,7 +305,7 @@ impl Storage {
             let v: String = row.get(1);
             let value = serde_json::from_str(&v)?;
             if strip {
-                results.insert(k.trim_left_matches(&key_prefix).to_string(), value);
+                results.insert(k[key_prefix.len()..].to_string(), value);
             } else {
                 results.insert(k, value);
             }
@@ -134,6 +329,75 @@ impl Storage {
         Ok(())
     }
 
+    /// Compare a mapping with the values stored under a prefix.  Returns a Delta, keyed
+    /// without the prefix, for every key whose value differs.  Keys stored under the prefix
+    /// that are missing from the mapping are included with a current value of None.
+    pub fn delta<T>(&self,
+                    mapping: &HashMap<String, T>,
+                    prefix: &str)
+                    -> Result<HashMap<String, Delta>, JujuError>
+        where T: Serialize
+    {
+        let mut previous = self.getrange(prefix, true)?;
+        let mut deltas: HashMap<String, Delta> = HashMap::new();
+        for (k, v) in mapping {
+            let current = serde_json::to_value(v)?;
+            let previous = previous.remove(k);
+            if previous.as_ref() != Some(&current) {
+                deltas.insert(k.clone(),
+                              Delta {
+                                  previous,
+                                  current: Some(current),
+                              });
+            }
+        }
+        // Whatever is left was stored before but is gone now
+        for (k, v) in previous {
+            deltas.insert(k,
+                          Delta {
+                              previous: Some(v),
+                              current: None,
+                          });
+        }
+        Ok(deltas)
+    }
+
+    /// Store a mapping under a prefix, replacing everything previously stored under it,
+    /// and return how it differs from what was stored.  See `delta`
+    pub fn snapshot<T>(&self,
+                       mapping: HashMap<String, T>,
+                       prefix: &str)
+                       -> Result<HashMap<String, Delta>, JujuError>
+        where T: Serialize
+    {
+        let deltas = self.delta(&mapping, prefix)?;
+        let removed: Vec<String> = deltas.iter()
+            .filter(|&(_, d)| d.current.is_none())
+            .map(|(k, _)| format!("{}{}", prefix, k))
+            .collect();
+        if !removed.is_empty() {
+            self.unsetrange(Some(removed), None)?;
+        }
+        self.update(mapping, Some(prefix.to_string()))?;
+        Ok(deltas)
+    }
+
+    /// Snapshot the charm's current config values under `config.` and return which
+    /// options changed since the last snapshot
+    pub fn snapshot_config(&self) -> Result<HashMap<String, Delta>, JujuError> {
+        self.snapshot(config_get_all()?, "config.")
+    }
+
+    /// Snapshot a unit's relation data, ie: as returned by relation-get, under
+    /// `rels.<relation id>.<unit>.` and return which keys changed since the last snapshot
+    pub fn snapshot_relation(&self,
+                             relation_id: &RelationId,
+                             unit: &UnitName,
+                             data: HashMap<String, String>)
+                             -> Result<HashMap<String, Delta>, JujuError> {
+        self.snapshot(data, &format!("rels.{}.{}.", relation_id, unit))
+    }
+
     /// Remove a key from the database entirely.
     pub fn unset(&self, key: &str) -> Result<(), JujuError> {
         let rowcount = self.conn.execute("delete from kv where key=?", &[&key])?;
@@ -191,8 +455,8 @@ impl Storage {
             }
             None => {
                 let rowcount = self.conn
-                    .execute("delete from kv where key like ?",
-                             &[&format!("{}%", prefix)])?;
+                    .execute("delete from kv where substr(key, 1, ?) = ?",
+                             &[&(prefix.chars().count() as i64), &prefix])?;
                 if self.revision.is_some() && rowcount > 0 {
                     self.conn
                         .execute("insert into kv_revisions values (?, ?, ?)",
@@ -250,19 +514,32 @@ impl Storage {
 
         return Ok(());
     }
-    /*
-    ///Scope all future interactions to the current hook execution
-    ///revision
-    pub fn hook_scope(&mut self, name: &str) ->Result<String,JujuError>{
-        assert!(self.revision.is_none());
-        let _ = self.conn.execute("insert into hooks (hook, date) values (?, ?)",
-            &[name or sys.argv[0],
-             datetime.datetime.utcnow().isoformat()])?;
-            self.revision = self.cursor.lastrowid;
-            yield self.revision;
-            self.revision = None
+    /// Scope all future interactions to the current hook execution
+    /// revision.  Records the hook name, or argv[0] if no name is given, and the
+    /// current UTC date.  Writes made through the returned guard are recorded in
+    /// the key's history under that revision.
+    pub fn hook_scope(&mut self, name: Option<&str>) -> Result<HookScope<'_>, JujuError> {
+        if self.revision.is_some() {
+            let msg = format!("Already inside the scope of revision {:?}", self.revision);
+            return Err(JujuError::InvalidState(msg));
+        }
+        let hook = match name {
+            Some(name) => name.to_string(),
+            None => env::args().next().unwrap_or_default(),
+        };
+        self.conn.execute("insert into hooks (hook, date) values (?, ?)",
+                          &[&hook, &Utc::now()])?;
+        self.revision = Some(self.conn.last_insert_rowid() as u32);
+        Ok(HookScope { storage: self })
+    }
+
+    /// Run a closure inside a hook scope.  See `hook_scope`
+    pub fn with_hook_scope<F, T>(&mut self, name: Option<&str>, f: F) -> Result<T, JujuError>
+        where F: FnOnce(&mut Storage) -> Result<T, JujuError>
+    {
+        let mut scope = self.hook_scope(name)?;
+        f(&mut scope)
     }
-    */
 
     pub fn gethistory(&self, key: &str) -> Result<Vec<History>, JujuError> {
         let mut results: Vec<History> = Vec::new();
@@ -0,0 +1,228 @@
+extern crate juju;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use juju::JujuError;
+use juju::backend::{reset_backend, set_backend};
+use juju::network::NetworkInfo;
+use juju::{HookToolBackend, HookToolOutput, ProcessBackend, StatusType};
+
+// network-get output from a Juju 2.9 agent, including the keys kept for older charms
+const NETWORK_GET: &str = r#"{"bind-addresses":[{"mac-address":"00:16:3e:8d:1a:2b",
+"interface-name":"eth1","addresses":[{"hostname":"","address":"10.20.0.5",
+"cidr":"10.20.0.0/24","value":"10.20.0.5"}],"macaddress":"00:16:3e:8d:1a:2b",
+"interfacename":"eth1"}],"egress-subnets":["10.20.0.5/32"],
+"ingress-addresses":["10.20.0.5","10.30.0.5"]}"#;
+
+#[derive(Default)]
+struct Recorder {
+    calls: RefCell<Vec<(String, Vec<String>)>>,
+}
+
+impl HookToolBackend for Recorder {
+    fn invoke(&self, tool: &str, args: &[String]) -> Result<HookToolOutput, JujuError> {
+        self.calls.borrow_mut().push((tool.to_string(), args.to_vec()));
+        match tool {
+            "config-get" => Ok(HookToolOutput::from_stdout("8080\n")),
+            "is-leader" => Ok(HookToolOutput::from_stdout("True\n")),
+            "network-get" => Ok(HookToolOutput::from_stdout(NETWORK_GET)),
+            "status-get" => {
+                Ok(HookToolOutput::from_stdout(r#"{"message":"hook failed: \"install\"",
+                    "status":"error","status-data":{"hook":"install","attempt":2}}"#))
+            }
+            _ => Ok(HookToolOutput::from_failure(1, "unknown tool")),
+        }
+    }
+}
+
+#[test]
+fn wrappers_use_installed_backend() {
+    let recorder = Rc::new(Recorder::default());
+    set_backend(recorder.clone());
+
+    assert_eq!(juju::config_get("port").unwrap(), Some("8080".to_string()));
+    assert!(juju::is_leader().unwrap());
+    assert!(juju::add_metric("users", "10").is_err());
+
+    reset_backend();
+    let calls = recorder.calls.borrow();
+    assert_eq!(calls[0], ("config-get".to_string(), vec!["port".to_string()]));
+    assert_eq!(calls[1], ("is-leader".to_string(), vec![]));
+    assert_eq!(calls[2], ("add-metric".to_string(), vec!["users=10".to_string()]));
+}
+
+#[test]
+fn reboot_runs_through_sudo() {
+    let recorder = Rc::new(Recorder::default());
+    set_backend(recorder.clone());
+    let _ = juju::reboot();
+    reset_backend();
+
+    let calls = recorder.calls.borrow();
+    assert_eq!(calls[0], ("sudo".to_string(), vec!["juju-reboot".to_string()]));
+}
+
+#[test]
+fn failing_tools_report_structured_errors() {
+    set_backend(Rc::new(Recorder::default()));
+    let err = juju::relation_get("host").unwrap_err();
+    reset_backend();
+
+    match err {
+        JujuError::HookTool { ref tool, ref args, exit_code, ref stderr } => {
+            assert_eq!(tool, "relation-get");
+            assert_eq!(args, &vec!["host".to_string()]);
+            assert_eq!(exit_code, Some(1));
+            assert_eq!(stderr, "unknown tool");
+        }
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+    assert_eq!(err.to_string(),
+               "relation-get host failed with exit code 1: unknown tool");
+}
+
+#[test]
+fn relation_settings_files_are_private() {
+    // Records the permissions of the settings file and its directory, then fails
+    #[derive(Default)]
+    struct Inspector {
+        seen: RefCell<Vec<(PathBuf, u32, u32)>>,
+    }
+    impl HookToolBackend for Inspector {
+        fn invoke(&self, _: &str, args: &[String]) -> Result<HookToolOutput, JujuError> {
+            let path = PathBuf::from(args.last().unwrap());
+            let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o777;
+            let dir_mode = mode(path.parent().unwrap());
+            self.seen.borrow_mut().push((path.clone(), mode(&path), dir_mode));
+            Ok(HookToolOutput::from_failure(1, "permission denied"))
+        }
+    }
+    let inspector = Rc::new(Inspector::default());
+    set_backend(inspector.clone());
+    let mut settings = HashMap::new();
+    settings.insert("password".to_string(), "hunter2".to_string());
+    let result = juju::relation_set_many(&settings);
+    reset_backend();
+
+    assert!(result.is_err());
+    let seen = inspector.seen.borrow();
+    let (ref path, file_mode, dir_mode) = seen[0];
+    assert_eq!((file_mode, dir_mode), (0o600, 0o700));
+    // Removed even though relation-set failed
+    assert!(!path.exists());
+    assert!(!path.parent().unwrap().exists());
+}
+
+#[test]
+fn missing_tools_report_io_errors() {
+    let err = ProcessBackend.invoke("juju-hook-tool-that-does-not-exist", &[]).unwrap_err();
+    match err {
+        JujuError::IoError(ref e) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn network_get_parses_bindings() {
+    let recorder = Rc::new(Recorder::default());
+    set_backend(recorder.clone());
+    let id = "db:4".parse().unwrap();
+    let info = juju::network_get("db", Some(&id)).unwrap();
+    let ingress = juju::ingress_address("db").unwrap();
+    let bind = juju::bind_address("db").unwrap();
+    reset_backend();
+
+    assert_eq!(recorder.calls.borrow()[0].1,
+               vec!["db".to_string(), "-r".to_string(), "db:4".to_string(),
+                    "--format=json".to_string()]);
+    assert_eq!(info.bind_addresses[0].interface_name, "eth1");
+    assert_eq!(info.bind_addresses[0].addresses[0].cidr, "10.20.0.0/24");
+    assert_eq!(info.egress_subnets, vec!["10.20.0.5/32".to_string()]);
+    assert_eq!(ingress.to_string(), "10.20.0.5");
+    assert_eq!(bind.to_string(), "10.20.0.5");
+}
+
+#[test]
+fn ingress_address_skips_hostnames() {
+    let harness = juju::testing::Harness::new("wordpress/0");
+    let network = |addresses: &[&str]| NetworkInfo {
+        ingress_addresses: addresses.iter().map(|a| a.to_string()).collect(),
+        ..NetworkInfo::default()
+    };
+    harness.set_network("db", network(&["db.example.com", "10.30.0.5"]));
+    assert_eq!(juju::ingress_address("db").unwrap().to_string(), "10.30.0.5");
+
+    harness.set_network("db", network(&["db.example.com"]));
+    match juju::ingress_address("db") {
+        Err(JujuError::Parse(msg)) => assert_eq!(msg, "no ingress address for endpoint db"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn status_get_parses_status_and_data() {
+    let recorder = Rc::new(Recorder::default());
+    set_backend(recorder.clone());
+    let status = juju::status_get().unwrap();
+    reset_backend();
+
+    assert_eq!(recorder.calls.borrow()[0].1,
+               vec!["--format=json".to_string(), "--include-data".to_string()]);
+    assert_eq!(status.status_type, StatusType::Error);
+    assert_eq!(status.message, "hook failed: \"install\"");
+    assert_eq!(status.data.get("hook"), Some(&"install".to_string()));
+    assert_eq!(status.data.get("attempt"), Some(&"2".to_string()));
+
+    assert_eq!("terminated".parse::<StatusType>().unwrap(), StatusType::Terminated);
+    match "Active".parse::<StatusType>() {
+        Err(JujuError::Parse(msg)) => assert_eq!(msg, "unknown status type \"Active\""),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn malformed_output_is_a_parse_error() {
+    struct Garbled;
+    impl HookToolBackend for Garbled {
+        fn invoke(&self, tool: &str, _: &[String]) -> Result<HookToolOutput, JujuError> {
+            match tool {
+                "storage-get" => Ok(HookToolOutput::from_stdout(r#"{"kind":"tape"}"#)),
+                "config-get" | "status-get" => Ok(HookToolOutput::from_stdout("[]")),
+                _ => Ok(HookToolOutput::from_stdout("host: 10.0.0.2\n")),
+            }
+        }
+    }
+    set_backend(Rc::new(Garbled));
+    let config = juju::config_get_all_as::<HashMap<String, String>>();
+    let storage = juju::storage_get_all(&"data/0".parse().unwrap());
+    let status = juju::status_get();
+    // Output that isn't JSON at all
+    let network = juju::network_get("db", None);
+    let relation = juju::relation_get_all();
+    reset_backend();
+
+    for result in vec![config.map(|_| ()), storage.map(|_| ()), status.map(|_| ())] {
+        match result {
+            Err(JujuError::Parse(_)) => {}
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+    match network {
+        Err(JujuError::Parse(ref msg)) => {
+            assert!(msg.starts_with("network-get printed invalid JSON: "), "{}", msg)
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+    match relation {
+        Err(JujuError::Parse(ref msg)) => {
+            assert!(msg.starts_with("relation-get printed invalid JSON: "), "{}", msg)
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
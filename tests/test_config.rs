@@ -0,0 +1,196 @@
+extern crate juju;
+#[macro_use]
+extern crate serde_derive;
+
+use std::collections::HashMap;
+
+use juju::{Config, HookContext, HookRegistry, JujuError};
+use juju::config::{ConfigSchema, ConfigViolation, OptionType};
+use juju::testing::Harness;
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct CharmConfig {
+    port: u16,
+    ratio: f64,
+    debug: bool,
+    motd: Option<String>,
+}
+
+#[test]
+fn typed_config() {
+    let harness = Harness::new("web/0");
+    harness.set_config("port", 8080);
+    harness.set_config("ratio", 0.5);
+    harness.set_config("debug", true);
+    harness.set_config("motd", "hello: world\nsecond line");
+    harness.set_config("unused", "ignored");
+
+    let config: CharmConfig = juju::config_get_all_as().unwrap();
+    assert_eq!(config,
+               CharmConfig {
+                   port: 8080,
+                   ratio: 0.5,
+                   debug: true,
+                   motd: Some("hello: world\nsecond line".to_string()),
+               });
+
+    // The untyped map keeps multi-line values and colons intact
+    let all = juju::config_get_all().unwrap();
+    assert_eq!(all.get("motd"), Some(&"hello: world\nsecond line".to_string()));
+    assert_eq!(all.get("debug"), Some(&"true".to_string()));
+}
+
+#[test]
+fn typed_config_errors_name_the_key() {
+    let harness = Harness::new("web/0");
+    harness.set_config("port", "not a number");
+    harness.set_config("ratio", 0.5);
+    harness.set_config("debug", true);
+
+    let err = juju::config_get_all_as::<CharmConfig>().unwrap_err();
+    assert!(err.to_string().contains("config option `port`"),
+            "unexpected error: {}",
+            err);
+
+    harness.unset_config("port");
+    let err = juju::config_get_all_as::<CharmConfig>().unwrap_err();
+    assert!(err.to_string().contains("missing field `port`"),
+            "unexpected error: {}",
+            err);
+}
+
+const CONFIG_YAML: &str = "
+options:
+  port:
+    type: int
+    default: 80
+    description: Port to listen on
+  ratio:
+    type: float
+    default: 0.5
+  debug:
+    type: boolean
+    default: false
+  admin_password:
+    description: Password for the admin user
+";
+
+#[test]
+fn schema_parses_options() {
+    let schema: ConfigSchema = CONFIG_YAML.parse().unwrap();
+    let port = schema.option("port").unwrap();
+    assert_eq!(port.option_type, OptionType::Int);
+    assert_eq!(port.default, Some("80".to_string()));
+    assert_eq!(port.description, Some("Port to listen on".to_string()));
+    assert_eq!(schema.default("ratio"), Some("0.5"));
+    assert_eq!(schema.default("debug"), Some("false"));
+
+    let password = schema.option("admin_password").unwrap();
+    assert_eq!(password.option_type, OptionType::String);
+    assert_eq!(password.default, None);
+    assert_eq!(schema.options().len(), 4);
+
+    assert!("options:\n  port:\n    type: integer\n".parse::<ConfigSchema>().is_err());
+}
+
+#[test]
+fn schema_validates_values() {
+    let schema: ConfigSchema = CONFIG_YAML.parse().unwrap();
+    let mut values = HashMap::new();
+    values.insert("port".to_string(), "eighty".to_string());
+    values.insert("debug".to_string(), "true".to_string());
+    values.insert("colour".to_string(), "blue".to_string());
+
+    assert_eq!(schema.validate(&values),
+               vec![ConfigViolation::UnknownKey("colour".to_string()),
+                    ConfigViolation::TypeMismatch {
+                        key: "port".to_string(),
+                        expected: OptionType::Int,
+                        value: "eighty".to_string(),
+                    }]);
+    assert_eq!(schema.unset_options(&values), vec!["admin_password".to_string()]);
+
+    values.insert("admin_password".to_string(), "secret".to_string());
+    assert!(schema.unset_options(&values).is_empty());
+}
+
+#[test]
+fn config_tracks_changes_between_hooks() {
+    let harness = Harness::new("web/0");
+    let snapshot = std::env::temp_dir().join(format!("juju-persistent-config-{}",
+                                                     std::process::id()));
+    let _ = std::fs::remove_file(&snapshot);
+    harness.set_config("port", 80);
+    harness.set_config("motd", "hello");
+
+    // Everything is new on the first hook
+    let config = Config::load_from(snapshot.clone()).unwrap();
+    assert!(config.changed("port"));
+    assert_eq!(config.changed_keys(), vec!["motd".to_string(), "port".to_string()]);
+
+    // A failed hook does not commit, so the next hook still sees the changes
+    let config = Config::load_from(snapshot.clone()).unwrap();
+    assert_eq!(config.previous("port"), None);
+    config.commit().unwrap();
+
+    harness.set_config("port", 8080);
+    harness.unset_config("motd");
+    harness.set_config("debug", true);
+    let config = Config::load_from(snapshot.clone())
+        .unwrap()
+        .with_schema(CONFIG_YAML.parse().unwrap());
+    assert!(config.changed("port"));
+    assert!(!config.changed("ratio"));
+    assert_eq!(config.get("port"), Some("8080".to_string()));
+    assert_eq!(config.get("ratio"), Some("0.5".to_string()));
+    assert_eq!(config.previous("port"), Some("80".to_string()));
+    assert_eq!(config.unset_options(), vec!["admin_password".to_string()]);
+
+    let diff = config.diff();
+    assert_eq!(diff.added.get("debug"), Some(&"true".to_string()));
+    assert_eq!(diff.removed.get("motd"), Some(&"hello".to_string()));
+    assert_eq!(diff.modified.get("port"),
+               Some(&("80".to_string(), "8080".to_string())));
+    config.commit().unwrap();
+
+    let config = Config::load_from(snapshot.clone()).unwrap();
+    assert!(config.diff().is_empty());
+    std::fs::remove_file(&snapshot).unwrap();
+}
+
+#[test]
+fn dispatched_hooks_commit_config_on_success() {
+    let harness = Harness::new("web/0");
+    let snapshot = std::env::temp_dir().join(format!("juju-dispatched-config-{}",
+                                                     std::process::id()));
+    let _ = std::fs::remove_file(&snapshot);
+    harness.set_config("port", 80);
+
+    let path = snapshot.clone();
+    let mut registry = HookRegistry::new()
+        .on("config-changed", move |_| {
+            Config::load_from(path.clone())?;
+            Ok(())
+        })
+        .on("upgrade-charm", |_| Ok(()));
+    let context = |hook: &str| HookContext::from_vars(vec![("JUJU_HOOK_NAME", hook)]).unwrap();
+
+    // A Config loaded in a failed hook is not committed
+    let path = snapshot.clone();
+    let mut failing = HookRegistry::new().on("config-changed", move |_| {
+        Config::load_from(path.clone())?;
+        Err(JujuError::HookFailed("cannot configure".to_string()))
+    });
+    assert!(failing.dispatch(&context("config-changed")).is_err());
+    assert!(!snapshot.exists());
+
+    registry.dispatch(&context("config-changed")).unwrap();
+    assert!(!Config::load_from(snapshot.clone()).unwrap().changed("port"));
+
+    // A Config loaded outside the hook is not committed by a later one
+    harness.set_config("port", 8080);
+    assert!(Config::load_from(snapshot.clone()).unwrap().changed("port"));
+    registry.dispatch(&context("upgrade-charm")).unwrap();
+    assert!(Config::load_from(snapshot.clone()).unwrap().changed("port"));
+    std::fs::remove_file(&snapshot).unwrap();
+}
@@ -0,0 +1,91 @@
+extern crate juju;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use juju::{HookContext, JujuError};
+
+#[test]
+fn context_reads_every_variable() {
+    let mut vars = HashMap::new();
+    vars.insert("JUJU_HOOK_NAME", "db-relation-departed");
+    vars.insert("JUJU_UNIT_NAME", "wordpress/1");
+    vars.insert("JUJU_RELATION", "db");
+    vars.insert("JUJU_RELATION_ID", "db:4");
+    vars.insert("JUJU_REMOTE_UNIT", "mysql/2");
+    vars.insert("JUJU_REMOTE_APP", "mysql");
+    vars.insert("JUJU_DEPARTING_UNIT", "mysql/2");
+    vars.insert("JUJU_MODEL_NAME", "prod");
+    vars.insert("JUJU_MODEL_UUID", "8f2c0e3a-5d1b-4c7e-9a6f-2b3d4e5f6a7b");
+    vars.insert("CHARM_DIR", "/var/lib/juju/agents/unit-wordpress-1/charm");
+    vars.insert("JUJU_PRINCIPAL_UNIT", "");
+    vars.insert("JUJU_MACHINE_ID", "0/lxd/3");
+    vars.insert("JUJU_AVAILABILITY_ZONE", "us-east-1a");
+    vars.insert("JUJU_VERSION", "2.9.42");
+    vars.insert("PATH", "/usr/bin");
+
+    let context = HookContext::from_vars(vars).unwrap();
+    assert_eq!(context.hook_name, Some("db-relation-departed".to_string()));
+    assert_eq!(context.unit_name, Some("wordpress/1".parse().unwrap()));
+    assert_eq!(context.relation_name, Some("db".to_string()));
+    assert_eq!(context.relation_id, Some("db:4".parse().unwrap()));
+    assert_eq!(context.remote_unit, Some("mysql/2".parse().unwrap()));
+    assert_eq!(context.remote_app, Some("mysql".parse().unwrap()));
+    assert_eq!(context.departing_unit, context.remote_unit);
+    assert_eq!(context.model_name, Some("prod".to_string()));
+    assert_eq!(context.charm_dir,
+               Some(PathBuf::from("/var/lib/juju/agents/unit-wordpress-1/charm")));
+    assert_eq!(context.principal_unit, None);
+    assert_eq!(context.machine_id, Some("0/lxd/3".to_string()));
+    assert_eq!(context.juju_version, Some("2.9.42".to_string()));
+    assert!(context.is_relation_hook());
+    assert!(!context.is_action());
+
+    let empty = HookContext::from_vars(Vec::<(String, String)>::new()).unwrap();
+    assert_eq!(empty, HookContext::default());
+}
+
+#[test]
+fn context_rejects_malformed_names() {
+    let err = HookContext::from_vars(vec![("JUJU_UNIT_NAME", "wordpress")]).unwrap_err();
+    match err {
+        JujuError::InvalidName(ref msg) => {
+            assert!(msg.starts_with("JUJU_UNIT_NAME: "), "{}", msg)
+        }
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn context_ignores_unexpected_remote_names() {
+    let harness = juju::testing::Harness::new("wordpress/0");
+    // Cross model relations name the remote application remote-<uuid>
+    let context = HookContext::from_vars(vec![("JUJU_HOOK_NAME", "db-relation-changed"),
+                                              ("JUJU_RELATION_ID", "db:4"),
+                                              ("JUJU_REMOTE_APP", "remote-1234567890"),
+                                              ("JUJU_REMOTE_UNIT", "remote-1234567890/0")])
+        .unwrap();
+    assert_eq!(context.relation_id, Some("db:4".parse().unwrap()));
+    assert_eq!((context.remote_app, context.remote_unit), (None, None));
+    let logs = harness.logs();
+    assert_eq!(logs.len(), 2);
+    assert!(logs[0].starts_with("WARN: ignoring JUJU_REMOTE_UNIT: invalid application name"),
+            "{}",
+            logs[0]);
+    assert!(logs[1].starts_with("WARN: ignoring JUJU_REMOTE_APP: invalid application name"),
+            "{}",
+            logs[1]);
+}
+
+#[test]
+fn context_from_env_reads_the_backend() {
+    let harness = juju::testing::Harness::new("wordpress/0");
+    harness.set_var("JUJU_HOOK_NAME", "upgrade-charm");
+    harness.set_var("JUJU_VERSION", "3.1.6");
+
+    let context = HookContext::from_env().unwrap();
+    assert_eq!(context.hook_name, Some("upgrade-charm".to_string()));
+    assert_eq!(context.unit_name, Some("wordpress/0".parse().unwrap()));
+    assert_eq!(context.juju_version, Some("3.1.6".to_string()));
+    assert_eq!(context.relation_id, None);
+}
@@ -0,0 +1,94 @@
+extern crate juju;
+
+use std::collections::HashMap;
+
+use juju::{ApplicationName, JujuError, UnitName};
+use juju::endpoints::{Metadata, Role};
+use juju::testing::Harness;
+
+const METADATA: &str = "
+name: wordpress
+provides:
+  website:
+    interface: http
+  logging: syslog
+requires:
+  db:
+    interface: mysql
+    limit: 1
+  juju-info:
+    interface: juju-info
+    scope: container
+peers:
+  cluster:
+    interface: wordpress-peers
+";
+
+#[test]
+fn metadata_declares_endpoints() {
+    let metadata: Metadata = METADATA.parse().unwrap();
+    assert_eq!(metadata.name, Some("wordpress".to_string()));
+
+    let names: Vec<&str> = metadata.endpoints().iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, vec!["cluster", "db", "juju-info", "logging", "website"]);
+
+    let db = metadata.endpoint("db").unwrap();
+    assert_eq!((db.role, db.interface.as_str(), db.limit), (Role::Requires, "mysql", Some(1)));
+    assert_eq!(metadata.endpoint("logging").unwrap().interface, "syslog");
+    assert_eq!(metadata.endpoint("juju-info").unwrap().scope, Some("container".to_string()));
+    assert_eq!(metadata.endpoints_with_role(Role::Peers)[0].name, "cluster");
+    assert!(metadata.endpoint("missing").is_none());
+
+    assert!("requires:\n  db: {}\n".parse::<Metadata>().is_err());
+    assert!("provides:\n  db: mysql\nrequires:\n  db: mysql\n".parse::<Metadata>().is_err());
+    for limit in &["-1", "1.5", "one"] {
+        let yaml = format!("requires:\n  db:\n    interface: mysql\n    limit: {}\n", limit);
+        match yaml.parse::<Metadata>() {
+            Err(JujuError::Parse(msg)) => {
+                assert!(msg.starts_with("endpoint db has an invalid limit"), "{}", msg)
+            }
+            other => panic!("unexpected result for limit {}: {:?}", limit, other),
+        }
+    }
+}
+
+#[test]
+fn endpoint_reads_and_writes_relation_data() {
+    let metadata: Metadata = METADATA.parse().unwrap();
+    let db = metadata.endpoint("db").unwrap();
+    let harness = Harness::new("wordpress/0");
+    assert!(db.relations().unwrap().is_empty());
+    assert!(!db.is_joined().unwrap());
+
+    let id = harness.add_relation("db");
+    assert_eq!(db.relations().unwrap(), vec![id.clone()]);
+    assert!(!db.is_joined().unwrap());
+
+    let mut data = HashMap::new();
+    data.insert("host".to_string(), "10.0.0.2".to_string());
+    harness.add_relation_unit(&id, "mysql/0", data.clone());
+    let mut app_data = HashMap::new();
+    app_data.insert("database".to_string(), "wordpress".to_string());
+    harness.set_relation_app_data(&id, "mysql", app_data.clone());
+
+    let mysql: UnitName = "mysql/0".parse().unwrap();
+    assert!(db.is_joined().unwrap());
+    assert_eq!(db.joined_units().unwrap(), vec![mysql.clone()]);
+    assert_eq!(db.get(&mysql, "host").unwrap(), Some("10.0.0.2".to_string()));
+    assert_eq!(db.unit_data(&mysql).unwrap(), data);
+    let app: ApplicationName = "mysql".parse().unwrap();
+    assert_eq!(db.app_data(&app).unwrap(), app_data);
+
+    // Units and applications that are not on the endpoint are errors, not empty data
+    assert!(db.unit_data(&"mysql/1".parse().unwrap()).is_err());
+    assert!(db.app_data(&"postgresql".parse().unwrap()).is_err());
+
+    let mut settings = HashMap::new();
+    settings.insert("user".to_string(), "wp".to_string());
+    db.set_unit_data(&settings).unwrap();
+    assert_eq!(harness.relation_data(&id, "wordpress/0"), settings);
+    assert!(db.set_app_data(&settings).is_err());
+    harness.set_leader(true);
+    db.set_app_data(&settings).unwrap();
+    assert_eq!(harness.relation_app_data(&id, "wordpress"), settings);
+}
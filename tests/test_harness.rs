@@ -0,0 +1,241 @@
+#[macro_use]
+extern crate juju;
+#[macro_use]
+extern crate serde_derive;
+
+use std::collections::HashMap;
+
+use juju::{ApplicationName, StorageId, UnitName};
+use juju::storage::StorageKind;
+use juju::testing::Harness;
+
+#[test]
+fn config_and_leadership() {
+    let harness = Harness::new("gluster/0");
+    harness.set_config("volume_name", "test");
+    harness.set_config("replicas", 3);
+
+    assert_eq!(juju::config_get("volume_name").unwrap(), Some("test".to_string()));
+    assert_eq!(juju::config_get("missing").unwrap(), None);
+    let all = juju::config_get_all().unwrap();
+    assert_eq!(all.get("replicas"), Some(&"3".to_string()));
+
+    assert!(!juju::is_leader().unwrap());
+    let mut settings = HashMap::new();
+    settings.insert("cluster_id".to_string(), "abc".to_string());
+    assert!(juju::leader_set(settings.clone()).is_err());
+
+    harness.set_leader(true);
+    juju::leader_set(settings).unwrap();
+    assert_eq!(juju::leader_get(Some("cluster_id".to_string())).unwrap(),
+               Some("abc".to_string()));
+    assert_eq!(harness.leader_settings().get("cluster_id"), Some(&"abc".to_string()));
+}
+
+#[test]
+fn relations() {
+    let harness = Harness::new("wordpress/0");
+    let id = harness.add_relation("db");
+    let mut data = HashMap::new();
+    data.insert("host".to_string(), "10.0.0.2".to_string());
+    harness.add_relation_unit(&id, "mysql/0", data);
+    // Outside a relation hook relation-ids needs an endpoint, as in Juju
+    assert!(juju::relation_ids().is_err());
+    harness.set_hook_relation(&id, Some("mysql/0"));
+    assert_eq!(juju::relation_ids().unwrap(), vec![id.clone()]);
+
+    let ids = juju::relation_ids_by_identifier("db").unwrap();
+    assert_eq!(ids.len(), 1);
+    assert_eq!(ids[0], id);
+    assert_eq!(ids[0].endpoint(), "db");
+    let units = juju::relation_list().unwrap();
+    assert_eq!(units, vec!["mysql/0".parse().unwrap()]);
+    assert_eq!(units[0].application().as_str(), "mysql");
+    assert_eq!(juju::relation_get("host").unwrap(), Some("10.0.0.2".to_string()));
+
+    juju::relation_set("database", "wp").unwrap();
+    assert_eq!(harness.relation_data(&id, "wordpress/0").get("database"),
+               Some(&"wp".to_string()));
+}
+
+#[test]
+fn status_ports_storage_and_logs() {
+    let harness = Harness::new("wordpress/0");
+    status_set!(Maintenance "installing");
+    assert_eq!(harness.status(),
+               Some(("maintenance".to_string(), "installing".to_string())));
+    let status = juju::status_get().unwrap();
+    assert_eq!((status.status_type, status.message.as_str()),
+               (juju::StatusType::Maintenance, "installing"));
+
+    let mut data = HashMap::new();
+    data.insert("hook".to_string(), "install".to_string());
+    harness.set_status("error", "hook failed: \"install\"", data.clone());
+    let status = juju::status_get().unwrap();
+    assert_eq!((status.status_type, status.data), (juju::StatusType::Error, data));
+    status_set!(Maintenance "retrying");
+    assert!(juju::status_get().unwrap().data.is_empty());
+    match juju::status_set(juju::Status::new(juju::StatusType::Error, "broken")) {
+        Err(juju::JujuError::InvalidStatus(juju::StatusType::Error)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(harness.status(),
+               Some(("maintenance".to_string(), "retrying".to_string())));
+
+    let ready = || juju::Status::new(juju::StatusType::Active, "3 units ready");
+    assert!(juju::application_status_set(ready()).is_err());
+    assert!(juju::application_status_get().is_err());
+    harness.set_leader(true);
+    assert_eq!(juju::application_status_get().unwrap().status_type,
+               juju::StatusType::Unknown);
+    juju::application_status_set(ready()).unwrap();
+    assert_eq!(juju::application_status_get().unwrap(), ready());
+    assert_eq!(harness.application_status(),
+               Some(("active".to_string(), "3 units ready".to_string())));
+
+    juju::open_port(80, juju::Transport::Tcp).unwrap();
+    juju::open_port(53, juju::Transport::Udp).unwrap();
+    juju::close_port(80, juju::Transport::Tcp).unwrap();
+    assert_eq!(harness.opened_ports(), vec!["53/udp".to_string()]);
+
+    harness.add_storage("data/0", "filesystem", "/srv/data");
+    harness.add_storage("logs/0", "block", "/dev/vdc");
+    assert_eq!(juju::storage_get("data/0").unwrap(), Some("/srv/data".to_string()));
+    let data: StorageId = "data/0".parse().unwrap();
+    assert_eq!(juju::storage_list(None).unwrap().len(), 2);
+    assert_eq!(juju::storage_list(Some("data")).unwrap(), vec![data.clone()]);
+    let instance = juju::storage_get_all(&data).unwrap();
+    assert_eq!((instance.kind, instance.location.as_str()),
+               (StorageKind::Filesystem, "/srv/data"));
+    assert!(juju::storage_get_all(&"data/1".parse().unwrap()).is_err());
+    assert!(juju::storage_get_location().is_err());
+    harness.set_hook_storage(Some("logs/0"));
+    assert_eq!(juju::storage_get_location().unwrap(), Some("/dev/vdc".to_string()));
+    juju::storage_add("data", 2).unwrap();
+    assert_eq!(harness.storage_added().get("data"), Some(&2));
+
+    juju::log("hello", Some(juju::LogLevel::Warn));
+    assert_eq!(harness.logs(), vec!["WARN: hello".to_string()]);
+}
+
+#[test]
+fn actions() {
+    let harness = Harness::new("wordpress/0");
+    let mut params = HashMap::new();
+    params.insert("target".to_string(), "/backup".to_string());
+    harness.set_action_params(params);
+
+    assert_eq!(juju::action_get("target").unwrap(), Some("/backup".to_string()));
+    juju::action_set("size", "10G").unwrap();
+    juju::action_fail("disk full").unwrap();
+    assert_eq!(harness.action_results().get("size"), Some(&"10G".to_string()));
+    assert_eq!(harness.action_failure(), Some("disk full".to_string()));
+
+    // The action and meter variables come from the model, not the process environment
+    assert!(juju::action_name().is_err());
+    harness.set_action("backup", "42");
+    assert_eq!(juju::action_name().unwrap(), "backup");
+    assert_eq!(juju::action_uuid().unwrap(), "42");
+    assert_eq!(juju::action_tag().unwrap(), "action-42");
+    harness.set_meter_status("AMBER", "over quota");
+    assert_eq!(juju::meter_status().unwrap(), "AMBER");
+    assert_eq!(juju::meter_info().unwrap(), "over quota");
+    harness.set_availability_zone("zone-b");
+    assert_eq!(juju::az_info().unwrap(), "zone-b");
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct MysqlInterface {
+    host: String,
+    port: Option<String>,
+}
+
+#[test]
+fn relation_data_bags() {
+    let harness = Harness::new("wordpress/0");
+    let id = harness.add_relation("db");
+    let mut data = HashMap::new();
+    data.insert("host".to_string(), "10.0.0.2".to_string());
+    data.insert("password".to_string(), "multi\nline: secret".to_string());
+    harness.add_relation_unit(&id, "mysql/0", data.clone());
+    harness.set_hook_relation(&id, Some("mysql/0"));
+
+    assert_eq!(juju::relation_get_all().unwrap(), data);
+    let unit: UnitName = "mysql/0".parse().unwrap();
+    assert_eq!(juju::relation_get_all_by_unit(&unit).unwrap(), data);
+    assert_eq!(juju::relation_get_all_by_id(&id, &unit).unwrap(), data);
+    assert_eq!(juju::relation_get_by_id("host", &id, &unit).unwrap(),
+               Some("10.0.0.2".to_string()));
+
+    let mysql: MysqlInterface = juju::relation_get_by_id_as(&id, &unit).unwrap();
+    assert_eq!(mysql,
+               MysqlInterface {
+                   host: "10.0.0.2".to_string(),
+                   port: None,
+               });
+    let missing: UnitName = "mysql/1".parse().unwrap();
+    let err = juju::relation_get_by_unit_as::<MysqlInterface>(&missing).unwrap_err();
+    assert!(err.to_string().contains("missing field `host`"), "{}", err);
+}
+
+#[test]
+fn relation_set_many_settings() {
+    let harness = Harness::new("wordpress/0");
+    let id = harness.add_relation("db");
+    harness.add_relation_unit(&id, "mysql/0", HashMap::new());
+    harness.set_hook_relation(&id, Some("mysql/0"));
+
+    let mut settings = HashMap::new();
+    settings.insert("database".to_string(), "wp".to_string());
+    settings.insert("ca".to_string(), "-----BEGIN CERTIFICATE-----\nMIIB\n".to_string());
+    juju::relation_set_many(&settings).unwrap();
+    assert_eq!(harness.relation_data(&id, "wordpress/0"), settings);
+
+    // Empty values unset keys
+    harness.clear_hook_relation();
+    let mut settings = HashMap::new();
+    settings.insert("ca".to_string(), "".to_string());
+    settings.insert("user".to_string(), "admin".to_string());
+    juju::relation_set_many_by_id(&settings, &id).unwrap();
+    let data = harness.relation_data(&id, "wordpress/0");
+    assert_eq!(data.get("ca"), None);
+    assert_eq!(data.get("user"), Some(&"admin".to_string()));
+    assert_eq!(data.get("database"), Some(&"wp".to_string()));
+
+    juju::relation_set_by_id("port", "3306", &id).unwrap();
+    assert_eq!(harness.relation_data(&id, "wordpress/0").get("port"),
+               Some(&"3306".to_string()));
+}
+
+#[test]
+fn application_data_bags() {
+    let harness = Harness::new("wordpress/0");
+    let id = harness.add_relation("db");
+    harness.add_relation_unit(&id, "mysql/0", HashMap::new());
+    let mut data = HashMap::new();
+    data.insert("host".to_string(), "10.0.0.2".to_string());
+    harness.set_relation_app_data(&id, "mysql", data.clone());
+    let mysql_app: ApplicationName = "mysql".parse().unwrap();
+    let wordpress_app = juju::local_application_name().unwrap();
+    assert_eq!(wordpress_app.as_str(), "wordpress");
+
+    assert_eq!(juju::relation_get_app(&id, &mysql_app).unwrap(), data);
+    let mysql: MysqlInterface = juju::relation_get_app_as(&id, &mysql_app).unwrap();
+    assert_eq!(mysql.host, "10.0.0.2");
+
+    // Only the leader may write, or read back, its own application's data bag
+    let mut settings = HashMap::new();
+    settings.insert("database".to_string(), "wp".to_string());
+    match juju::relation_set_app(&id, &settings) {
+        Err(juju::JujuError::NotLeader(_)) => {}
+        other => panic!("expected NotLeader, got {:?}", other),
+    }
+    assert!(juju::relation_get_app(&id, &wordpress_app).is_err());
+
+    harness.set_leader(true);
+    juju::relation_set_app(&id, &settings).unwrap();
+    assert_eq!(harness.relation_app_data(&id, "wordpress"), settings);
+    assert_eq!(juju::relation_get_app(&id, &wordpress_app).unwrap(), settings);
+    // The unit's own data bag is untouched
+    assert!(harness.relation_data(&id, "wordpress/0").is_empty());
+}
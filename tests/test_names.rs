@@ -0,0 +1,46 @@
+extern crate juju;
+
+use juju::{ApplicationName, JujuError, RelationId, StorageId, UnitName};
+
+#[test]
+fn names_round_trip() {
+    let id: RelationId = "cluster_peers:12".parse().unwrap();
+    assert_eq!(id.endpoint(), "cluster_peers");
+    assert_eq!(id.id(), 12);
+    assert_eq!(id.to_string(), "cluster_peers:12");
+    assert_eq!(RelationId::new("db", 3).unwrap(), "db:3".parse().unwrap());
+
+    let unit: UnitName = "mysql-router/7".parse().unwrap();
+    assert_eq!(unit.application().as_str(), "mysql-router");
+    assert_eq!(unit.number(), 7);
+    assert_eq!(unit.to_string(), "mysql-router/7");
+    assert_eq!(UnitName::new("mysql-router".parse().unwrap(), 7), unit);
+
+    let storage: StorageId = "osd-devices/2".parse().unwrap();
+    assert_eq!((storage.name(), storage.number()), ("osd-devices", 2));
+    assert_eq!(storage.to_string(), "osd-devices/2");
+}
+
+#[test]
+fn malformed_names_are_errors() {
+    for bad in &["db", "db:", ":3", "db:x", "DB:3", "db:-1"] {
+        assert!(bad.parse::<RelationId>().is_err(), "{} parsed", bad);
+    }
+    for bad in &["mysql", "mysql/", "/0", "mysql/zero", "mysql/0/1", "Mysql/0"] {
+        assert!(bad.parse::<UnitName>().is_err(), "{} parsed", bad);
+    }
+    for bad in &["", "9lives", "my--sql", "mysql-", "mysql-8", "my_sql"] {
+        assert!(bad.parse::<ApplicationName>().is_err(), "{} parsed", bad);
+    }
+    for bad in &["data", "data/", "/0", "data/x", "Data/0", "data:0"] {
+        assert!(bad.parse::<StorageId>().is_err(), "{} parsed", bad);
+    }
+
+    match "mysql".parse::<UnitName>() {
+        Err(ref err @ JujuError::InvalidName(_)) => {
+            assert_eq!(err.to_string(),
+                       "invalid unit name \"mysql\": expected <application>/<number>");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
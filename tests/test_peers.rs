@@ -0,0 +1,91 @@
+extern crate juju;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use juju::UnitName;
+use juju::endpoints::Metadata;
+use juju::network::NetworkInfo;
+use juju::peers::{MembershipChanges, Peers};
+use juju::testing::Harness;
+use juju::unitdata::{set_kv, Storage};
+
+fn unit(name: &str) -> UnitName {
+    name.parse().unwrap()
+}
+
+fn address(address: &str) -> HashMap<String, String> {
+    let mut data = HashMap::new();
+    data.insert("private-address".to_string(), address.to_string());
+    data
+}
+
+#[test]
+fn peers_track_cluster_membership() {
+    let metadata: Metadata = "
+name: gluster
+requires:
+  fuse: gluster-fuse
+peers:
+  server:
+    interface: gluster-peer
+"
+        .parse()
+        .unwrap();
+    let peers = Peers::from_metadata(&metadata).unwrap();
+    assert_eq!(peers.endpoint().name, "server");
+    let no_peers: Metadata = "name: gluster\n".parse().unwrap();
+    assert!(Peers::from_metadata(&no_peers).is_err());
+
+    let path = PathBuf::from("/tmp/peers_membership.db");
+    let _ = fs::remove_file(&path);
+    set_kv(Some(Storage::new(Some(path.clone())).unwrap()));
+
+    let harness = Harness::new("gluster/0");
+    harness.set_addresses("10.0.0.1", "192.0.2.1");
+    let id = harness.add_relation("server");
+    harness.add_relation_unit(&id, "gluster/1", address("10.0.0.2"));
+    let mut ingress = address("10.0.0.3");
+    ingress.insert("ingress-address".to_string(), "10.1.0.3".to_string());
+    harness.add_relation_unit(&id, "gluster/2", ingress);
+
+    let units = peers.units().unwrap();
+    let addresses: Vec<(String, Option<String>)> =
+        units.iter().map(|u| (u.name.to_string(), u.address.clone())).collect();
+    assert_eq!(addresses,
+               vec![("gluster/1".to_string(), Some("10.0.0.2".to_string())),
+                    ("gluster/2".to_string(), Some("10.1.0.3".to_string()))]);
+
+    // The peer endpoint's ingress address wins over the unit's private address
+    harness.set_network("server",
+                        NetworkInfo {
+                            ingress_addresses: vec!["10.1.0.1".to_string()],
+                            ..NetworkInfo::default()
+                        });
+    peers.publish_address().unwrap();
+    let mut settings = HashMap::new();
+    settings.insert("brick".to_string(), "/srv/brick".to_string());
+    peers.publish(&settings).unwrap();
+    let published = harness.relation_data(&id, "gluster/0");
+    assert_eq!(published.get("private-address"), Some(&"10.1.0.1".to_string()));
+    assert_eq!(published.get("brick"), Some(&"/srv/brick".to_string()));
+
+    assert_eq!(peers.membership_changes().unwrap(),
+               MembershipChanges {
+                   joined: vec![unit("gluster/1"), unit("gluster/2")],
+                   departed: vec![],
+               });
+    assert!(peers.membership_changes().unwrap().is_empty());
+
+    harness.remove_relation_unit(&id, "gluster/1");
+    harness.add_relation_unit(&id, "gluster/3", address("10.0.0.4"));
+    assert_eq!(peers.membership_changes().unwrap(),
+               MembershipChanges {
+                   joined: vec![unit("gluster/3")],
+                   departed: vec![unit("gluster/1")],
+               });
+
+    set_kv(None);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,126 @@
+extern crate juju;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use juju::{Event, HookContext, HookRegistry};
+use juju::reactive::{self, Reactive, ReactiveHandler};
+use juju::testing::Harness;
+use juju::unitdata::{set_kv, Storage};
+
+fn recorded(calls: &Rc<RefCell<Vec<String>>>, name: &'static str) -> ReactiveHandler {
+    let calls = calls.clone();
+    ReactiveHandler::new(name, move |_| {
+        calls.borrow_mut().push(name.to_string());
+        Ok(())
+    })
+}
+
+fn charm(calls: &Rc<RefCell<Vec<String>>>) -> HookRegistry {
+    let install_calls = calls.clone();
+    let db_calls = calls.clone();
+    let reactive = Reactive::new()
+        .handler(recorded(calls, "configure")
+            .when("app.installed")
+            .when("config.changed.port"))
+        .handler(ReactiveHandler::new("install", move |_| {
+                install_calls.borrow_mut().push("install".to_string());
+                reactive::set_flag("app.installed")
+            })
+            .when_not("app.installed"))
+        .handler(ReactiveHandler::new("db", move |_| {
+                db_calls.borrow_mut().push("db".to_string());
+                reactive::clear_flag("endpoint.db.changed")
+            })
+            .when("endpoint.db.joined")
+            .when("endpoint.db.changed"))
+        .handler(recorded(calls, "leader").when_any(&["leadership.is_leader", "never.set"]));
+    HookRegistry::new().reactive(reactive)
+}
+
+fn hook(name: &str) -> HookContext {
+    HookContext::from_vars(vec![("JUJU_HOOK_NAME", name)]).unwrap()
+}
+
+#[test]
+fn handlers_run_until_flags_settle() {
+    let path = PathBuf::from("/tmp/reactive_flags.db");
+    let _ = fs::remove_file(&path);
+    set_kv(Some(Storage::new(Some(path.clone())).unwrap()));
+    let harness = Harness::new("web/0");
+    harness.set_config("port", 80);
+    harness.set_leader(true);
+    let id = harness.add_relation("db");
+    let mut data = HashMap::new();
+    data.insert("host".to_string(), "10.0.0.2".to_string());
+    harness.add_relation_unit(&id, "mysql/0", data.clone());
+
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let mut registry = charm(&calls);
+
+    // install sets app.installed, which makes configure ready on the next pass
+    registry.dispatch(&hook("install")).unwrap();
+    assert_eq!(*calls.borrow(), vec!["install", "configure", "db", "leader"]);
+    assert!(reactive::is_flag_set("endpoint.db.joined").unwrap());
+    assert!(!reactive::is_flag_set("endpoint.db.changed").unwrap());
+    // config flags only last for the hook that saw the change
+    assert!(!reactive::is_flag_set("config.changed.port").unwrap());
+
+    calls.borrow_mut().clear();
+    registry.dispatch(&hook("update-status")).unwrap();
+    assert_eq!(*calls.borrow(), vec!["leader"]);
+
+    calls.borrow_mut().clear();
+    harness.set_config("port", 8080);
+    harness.set_leader(false);
+    data.insert("host".to_string(), "10.0.0.3".to_string());
+    harness.add_relation_unit(&id, "mysql/0", data);
+    // the charm's own snapshots don't hide changes from the automatic flags
+    juju::unitdata::with_kv(|kv| kv.snapshot_config()).unwrap();
+    registry.dispatch(&hook("config-changed")).unwrap();
+    assert_eq!(*calls.borrow(), vec!["configure", "db"]);
+
+    harness.remove_relation_unit(&id, "mysql/0");
+    registry.dispatch(&hook("db-relation-departed")).unwrap();
+    assert!(!reactive::is_flag_set("endpoint.db.joined").unwrap());
+    assert_eq!(reactive::get_flags().unwrap().into_iter().collect::<Vec<_>>(),
+               vec!["app.installed".to_string()]);
+
+    set_kv(None);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn reactive_handlers_are_kept_apart_from_registrations() {
+    let path = PathBuf::from("/tmp/reactive_apart.db");
+    let _ = fs::remove_file(&path);
+    set_kv(Some(Storage::new(Some(path.clone())).unwrap()));
+    let harness = Harness::new("web/0");
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let install_calls = calls.clone();
+    let mut registry = HookRegistry::new()
+        .reactive(Reactive::new().handler(recorded(&calls, "reactive")))
+        .on("install", move |_| {
+            install_calls.borrow_mut().push("install".to_string());
+            Ok(())
+        })
+        .on_action("backup", |_| Ok(()));
+
+    assert_eq!(registry.matching(&Event::Hook("install".to_string())),
+               vec!["install".to_string()]);
+    // Reactive handlers run after the others, on every hook but not on actions
+    registry.dispatch(&hook("install")).unwrap();
+    registry.dispatch(&hook("update-status")).unwrap();
+    registry.dispatch(&HookContext::from_vars(vec![("JUJU_ACTION_NAME", "backup")]).unwrap())
+        .unwrap();
+    assert_eq!(*calls.borrow(), vec!["install", "reactive", "reactive"]);
+    assert!(!harness.logs().iter().any(|line| line.starts_with("WARN")),
+            "{:?}",
+            harness.logs());
+
+    set_kv(None);
+    fs::remove_file(&path).unwrap();
+}
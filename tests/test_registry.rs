@@ -0,0 +1,324 @@
+#[macro_use]
+extern crate juju;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use juju::{ErrorPolicy, Event, HookContext, HookRegistry, JujuError, RelationEvent,
+           StorageEvent};
+use juju::{HookToolBackend, HookToolOutput};
+use juju::backend::{reset_backend, set_backend};
+use juju::testing::Harness;
+
+fn context(hook_name: &str) -> HookContext {
+    HookContext::from_vars(vec![("JUJU_HOOK_NAME", hook_name), ("JUJU_UNIT_NAME", "web/0")])
+        .unwrap()
+}
+
+fn legacy_stop() -> Result<(), String> {
+    Err("cannot stop".to_string())
+}
+
+fn legacy_ok() -> Result<(), String> {
+    Ok(())
+}
+
+fn legacy_config_changed() -> Result<(), String> {
+    Err("config-changed ran".to_string())
+}
+
+fn legacy_panic() -> Result<(), String> {
+    let replicas: Vec<u8> = Vec::new();
+    let _ = replicas[3];
+    Ok(())
+}
+
+#[test]
+fn closures_receive_the_context() {
+    let _harness = Harness::new("web/0");
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let (installs, changes) = (calls.clone(), calls.clone());
+    let mut registry = HookRegistry::new()
+        .on_many(&["install", "upgrade-charm"], move |context| {
+            installs.borrow_mut().push(format!("setup {}", context.hook_name.as_ref().unwrap()));
+            Ok(())
+        })
+        .on_pattern("*-relation-changed", move |context| {
+            changes.borrow_mut().push(format!("changed {}", context.hook_name.as_ref().unwrap()));
+            Ok(())
+        })
+        .hook(hook!("stop", legacy_stop));
+
+    registry.dispatch(&context("install")).unwrap();
+    registry.dispatch(&context("upgrade-charm")).unwrap();
+    registry.dispatch(&context("db-relation-changed")).unwrap();
+    assert_eq!(*calls.borrow(),
+               vec!["setup install".to_string(),
+                    "setup upgrade-charm".to_string(),
+                    "changed db-relation-changed".to_string()]);
+
+    match registry.dispatch(&context("stop")) {
+        Err(JujuError::HookFailed(ref msg)) => assert_eq!(msg, "cannot stop"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match registry.dispatch(&context("db-relation-joined")) {
+        Err(JujuError::UnknownHook(ref name)) => assert_eq!(name, "db-relation-joined"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn patterns_match_whole_names() {
+    let _harness = Harness::new("web/0");
+    let mut registry = HookRegistry::new()
+        .on_pattern("data-storage-*", |_| Ok(()))
+        .on_pattern("*-relation-*ed", |_| Ok(()));
+
+    assert!(registry.dispatch(&context("data-storage-attached")).is_ok());
+    assert!(registry.dispatch(&context("db-relation-departed")).is_ok());
+    assert!(registry.dispatch(&context("db-relation-broken")).is_err());
+    assert!(registry.dispatch(&context("logs-storage-attached")).is_err());
+}
+
+#[test]
+fn relation_and_storage_registrations() {
+    let harness = Harness::new("web/0");
+    let mut registry = HookRegistry::new()
+        .on_relation("db", RelationEvent::Joined, |_| Ok(()))
+        .on_relation("*", RelationEvent::Departed, |_| Ok(()))
+        .on_storage("data", StorageEvent::Attached, |_| Ok(()))
+        .on_pattern("*-departed", |_| Ok(()));
+
+    assert!(registry.dispatch(&context("db-relation-joined")).is_ok());
+    assert!(registry.dispatch(&context("cache-relation-joined")).is_err());
+    assert!(registry.dispatch(&context("data-storage-attached")).is_ok());
+    assert!(registry.dispatch(&context("data-storage-detaching")).is_err());
+    assert!(harness.logs().is_empty());
+
+    // Overlapping registrations all run, in registration order
+    assert_eq!(registry.matching(&Event::Hook("cache-relation-departed".to_string())),
+               vec!["pattern *-relation-departed".to_string(),
+                    "pattern *-departed".to_string()]);
+    assert!(registry.dispatch(&context("cache-relation-departed")).is_ok());
+    assert!(harness.logs().is_empty());
+
+    // An exact name that a pattern also matches is reported when it is registered, whichever
+    // comes first.  The reactive catch-all never is
+    let _registry = HookRegistry::new()
+        .on_pattern("*-changed", |_| Ok(()))
+        .on("config-changed", |_| Ok(()))
+        .on_relation("db", RelationEvent::Departed, |_| Ok(()))
+        .on_pattern("db-*", |_| Ok(()))
+        .reactive(juju::reactive::Reactive::new());
+    assert_eq!(harness.logs(),
+               vec!["WARN: hook config-changed is handled by both pattern *-changed and \
+                     config-changed"
+                        .to_string(),
+                    "WARN: hook db-relation-departed is handled by both db-relation-departed \
+                     and pattern db-*"
+                        .to_string()]);
+}
+
+#[test]
+fn storage_handlers_receive_the_instance() {
+    let harness = Harness::new("web/0");
+    harness.add_storage("data/1", "filesystem", "/srv/data/1");
+    let locations = Rc::new(RefCell::new(Vec::new()));
+    let seen = locations.clone();
+    let mut registry = HookRegistry::new()
+        .on_storage_instance("data", StorageEvent::Attached, move |_, instance| {
+            seen.borrow_mut().push((instance.id.to_string(), instance.location.clone()));
+            Ok(())
+        });
+
+    let attached = HookContext::from_vars(vec![("JUJU_HOOK_NAME", "data-storage-attached"),
+                                               ("JUJU_STORAGE_ID", "data/1")])
+        .unwrap();
+    registry.dispatch(&attached).unwrap();
+    assert_eq!(*locations.borrow(),
+               vec![("data/1".to_string(), "/srv/data/1".to_string())]);
+    assert!(registry.dispatch(&context("data-storage-attached")).is_err());
+}
+
+#[test]
+fn process_hooks_matches_names_exactly() {
+    let harness = Harness::new("web/0");
+    let config_changed = context("config-changed");
+    let hooks = vec![hook!("changed", legacy_ok),
+                     hook!("config", legacy_ok),
+                     hook!("config-changed", legacy_config_changed)];
+    assert_eq!(juju::process_hooks_with_context(hooks, &config_changed),
+               Err("config-changed ran".to_string()));
+
+    let hooks = vec![hook!("changed", legacy_ok)];
+    assert_eq!(juju::process_hooks_with_context(hooks, &config_changed),
+               Err("Warning: Unknown callback for hook config-changed".to_string()));
+
+    // Panics become errors and block the unit
+    let hooks = vec![hook!("config-changed", legacy_panic)];
+    let err = juju::process_hooks_with_context(hooks, &config_changed).unwrap_err();
+    assert!(err.starts_with("panicked: index out of bounds"), "{}", err);
+    let (status, message) = harness.status().unwrap();
+    assert_eq!(status, "blocked");
+    assert!(message.starts_with("hook config-changed panicked: index out of bounds"),
+            "{}",
+            message);
+}
+
+#[test]
+fn dispatch_path_routes_hooks_and_actions() {
+    let harness = Harness::new("web/0");
+    let mut registry = HookRegistry::new()
+        .on("install", |_| Ok(()))
+        .on("backup", |_| Err(JujuError::HookFailed("the backup hook ran".to_string())))
+        .on_action("backup", |context| {
+            assert!(context.is_action());
+            juju::action_set("path", "/srv/backup.tar").map(|_| ())
+        })
+        .on_action("restore", |_| Err(JujuError::HookFailed("no backup found".to_string())));
+
+    let dispatch = |path: &str| {
+        HookContext::from_vars(vec![("JUJU_DISPATCH_PATH", path),
+                                    // Juju still sets this under dispatch, but the path wins
+                                    ("JUJU_HOOK_NAME", "update-status")])
+            .unwrap()
+    };
+    assert!(registry.dispatch(&dispatch("hooks/install")).is_ok());
+    registry.dispatch(&dispatch("actions/backup")).unwrap();
+    assert_eq!(harness.action_results().get("path"), Some(&"/srv/backup.tar".to_string()));
+
+    // A failing action handler marks the action as failed
+    assert!(registry.dispatch(&dispatch("actions/restore")).is_err());
+    assert_eq!(harness.action_failure(), Some("no backup found".to_string()));
+
+    match registry.dispatch(&dispatch("actions/install")) {
+        Err(JujuError::UnknownAction(ref name)) => assert_eq!(name, "install"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    assert_eq!(Event::from_path("/var/lib/juju/agents/unit-web-0/charm/hooks/install"),
+               Event::Hook("install".to_string()));
+    assert_eq!(Event::from_path("/var/lib/juju/agents/unit-web-0/charm/actions/backup"),
+               Event::Action("backup".to_string()));
+    let action = HookContext::from_vars(vec![("JUJU_ACTION_NAME", "backup")]).unwrap();
+    assert_eq!(Event::from_context(&action), Event::Action("backup".to_string()));
+}
+
+#[test]
+fn action_fail_errors_keep_the_handler_error() {
+    struct NoActionFail;
+    impl HookToolBackend for NoActionFail {
+        fn invoke(&self, tool: &str, _: &[String]) -> Result<HookToolOutput, JujuError> {
+            match tool {
+                "action-fail" => Ok(HookToolOutput::from_failure(1, "not in an action")),
+                _ => Ok(HookToolOutput::from_stdout("")),
+            }
+        }
+    }
+    let mut registry = HookRegistry::new()
+        .on_action("restore", |_| Err(JujuError::HookFailed("no backup found".to_string())));
+    let action = HookContext::from_vars(vec![("JUJU_ACTION_NAME", "restore")]).unwrap();
+    set_backend(Rc::new(NoActionFail));
+    let result = registry.dispatch(&action);
+    reset_backend();
+
+    match result {
+        Err(JujuError::Multiple(ref errors)) => {
+            assert_eq!(errors.len(), 2);
+            assert_eq!(errors[0].to_string(), "no backup found");
+            assert!(errors[1].to_string().starts_with("action-fail no backup found failed"),
+                    "{}",
+                    errors[1]);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+fn recording_registry(calls: &Rc<RefCell<Vec<String>>>, policy: ErrorPolicy) -> HookRegistry {
+    let record = |calls: &Rc<RefCell<Vec<String>>>, what: &'static str| {
+        let calls = calls.clone();
+        move |_: &HookContext| {
+            calls.borrow_mut().push(what.to_string());
+            Ok(())
+        }
+    };
+    let (first_failure, after) = (calls.clone(), calls.clone());
+    HookRegistry::new()
+        .error_policy(policy)
+        .before(record(calls, "before"))
+        .on("config-changed", record(calls, "first"))
+        .on("config-changed", move |_| {
+            first_failure.borrow_mut().push("second".to_string());
+            Err(JujuError::HookFailed("second failed".to_string()))
+        })
+        .on("config-changed", |_| Err(JujuError::HookFailed("third failed".to_string())))
+        .after(move |_, errors| {
+            after.borrow_mut().push(format!("after {} errors", errors.len()));
+            Ok(())
+        })
+}
+
+#[test]
+fn every_handler_runs_between_middleware() {
+    let harness = Harness::new("web/0");
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let mut registry = recording_registry(&calls, ErrorPolicy::StopOnFirstError);
+    match registry.dispatch(&context("config-changed")) {
+        Err(JujuError::HookFailed(ref msg)) => assert_eq!(msg, "second failed"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(*calls.borrow(),
+               vec!["before", "first", "second", "after 1 errors"]);
+
+    calls.borrow_mut().clear();
+    let mut registry = recording_registry(&calls, ErrorPolicy::RunAll);
+    let err = registry.dispatch(&context("config-changed")).unwrap_err();
+    match err {
+        JujuError::Multiple(ref errors) => assert_eq!(errors.len(), 2),
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+    assert_eq!(err.to_string(), "2 errors: second failed; third failed");
+    assert_eq!(*calls.borrow(),
+               vec!["before", "first", "second", "after 2 errors"]);
+
+    // A failing before middleware skips the handlers but not the after middleware
+    calls.borrow_mut().clear();
+    let mut registry = recording_registry(&calls, ErrorPolicy::RunAll)
+        .before(|_| Err(JujuError::HookFailed("not ready".to_string())));
+    assert!(registry.dispatch(&context("config-changed")).is_err());
+    assert_eq!(*calls.borrow(), vec!["before", "after 1 errors"]);
+
+    // Several handlers for one hook are supported, so they are not worth a warning
+    assert!(harness.logs().is_empty());
+}
+
+#[test]
+fn panics_are_caught_and_logged() {
+    let harness = Harness::new("web/0");
+    let mut registry = HookRegistry::new()
+        .on("install", |_| panic!("install exploded"))
+        .after(|_, errors| {
+            assert_eq!(errors.len(), 1);
+            Ok(())
+        });
+
+    match registry.dispatch(&context("install")) {
+        Err(JujuError::Panic { ref message, ref backtrace }) => {
+            assert!(message.starts_with("install exploded at tests/test_registry.rs:"),
+                    "{}",
+                    message);
+            assert!(!backtrace.is_empty());
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let logs = harness.logs();
+    assert_eq!(logs.len(), 1);
+    assert!(logs[0].starts_with("ERROR: hook install panicked: install exploded at "),
+            "{}",
+            logs[0]);
+    // The status is only touched when asked to
+    assert_eq!(harness.status(), None);
+
+    // Panics outside of dispatch still reach the default hook and unwind as usual
+    assert!(std::panic::catch_unwind(|| panic!("outside dispatch")).is_err());
+}
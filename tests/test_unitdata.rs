@@ -1,10 +1,15 @@
 extern crate juju;
+extern crate serde_json;
 
 use std::collections::HashMap;
+use std::fs;
+use std::io;
 use std::path::PathBuf;
 
-use juju::JujuError;
+use juju::{HookContext, HookRegistry, JujuError};
+use juju::testing::Harness;
 use juju::unitdata::*;
+use serde_json::Value;
 
 #[test]
 fn get_set_delete_keys() {
@@ -46,4 +51,185 @@ fn get_set_delete_keys() {
                         None)
             .unwrap();
     assert_eq!(rows_deleted, 3);
+
+    // `_` and `%` in a prefix match only themselves, and case matters
+    unitdata.set("cluster_peers.a", 1).unwrap();
+    unitdata.set("clusterXpeers.b", 2).unwrap();
+    unitdata.set("CLUSTER_PEERS.c", 3).unwrap();
+    let results = unitdata.getrange("cluster_peers.", true).unwrap();
+    assert_eq!(results.keys().collect::<Vec<_>>(), vec!["a"]);
+    assert_eq!(unitdata.unsetrange(None, Some("cluster_peers.".to_string())).unwrap(), 1);
+    assert_eq!(unitdata.unsetrange(None, Some("clusterXpeers.".to_string())).unwrap(), 1);
+    assert_eq!(unitdata.unsetrange(None, Some("CLUSTER_PEERS.".to_string())).unwrap(), 1);
+}
+
+#[test]
+fn hook_scope_records_history() {
+    let path = PathBuf::from("/tmp/unitdata_history.db");
+    let _ = fs::remove_file(&path);
+    let mut unitdata = Storage::new(Some(path.clone())).expect("Failed to connect to database");
+
+    // Writes outside of a hook scope are not recorded
+    unitdata.set("foo", "unscoped").unwrap();
+    assert!(unitdata.gethistory("foo").unwrap().is_empty());
+
+    let first_revision = {
+        let scope = unitdata.hook_scope(Some("install")).unwrap();
+        scope.set("foo", "bar").unwrap();
+        scope.revision()
+    };
+    unitdata.with_hook_scope(Some("config-changed"), |kv| {
+            assert!(kv.hook_scope(None).is_err());
+            kv.set("foo", "baz")
+        })
+        .unwrap();
+    unitdata.set("foo", "unscoped again").unwrap();
+
+    let history = unitdata.gethistory("foo").unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].revision, first_revision);
+    assert_eq!(history[0].hook, "install");
+    assert_eq!(history[0].data, "bar");
+    assert_eq!(history[1].hook, "config-changed");
+    assert_eq!(history[1].data, "baz");
+    assert!(history[0].date <= history[1].date);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn transactions_commit_or_roll_back() {
+    let path = PathBuf::from("/tmp/unitdata_transactions.db");
+    let _ = fs::remove_file(&path);
+    let mut unitdata = Storage::new(Some(path.clone())).expect("Failed to connect to database");
+
+    unitdata.begin().unwrap();
+    assert!(unitdata.in_transaction());
+    unitdata.set("foo", "bar").unwrap();
+    unitdata.rollback().unwrap();
+    assert!(!unitdata.in_transaction());
+    let value: Option<String> = unitdata.get("foo").unwrap();
+    assert_eq!(value, None);
+
+    let result: Result<(), JujuError> = unitdata.transaction(|kv| {
+        kv.set("foo", "bar")?;
+        Err(JujuError::IoError(io::Error::new(io::ErrorKind::Other, "hook failed")))
+    });
+    assert!(result.is_err());
+    let value: Option<String> = unitdata.get("foo").unwrap();
+    assert_eq!(value, None);
+
+    unitdata.transaction(|kv| kv.set("foo", "baz")).unwrap();
+    let value: Option<String> = unitdata.get("foo").unwrap();
+    assert_eq!(value, Some("baz".to_string()));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn hook_writes_are_committed_only_on_success() {
+    let path = PathBuf::from("/tmp/unitdata_hooks.db");
+    let _ = fs::remove_file(&path);
+    set_kv(Some(Storage::new(Some(path.clone())).unwrap()));
+
+    begin_hook().unwrap();
+    with_kv(|kv| kv.set("attempt", 1)).unwrap();
+    end_hook(false).unwrap();
+    let value: Option<u32> = with_kv(|kv| kv.get("attempt")).unwrap();
+    assert_eq!(value, None);
+
+    begin_hook().unwrap();
+    with_kv(|kv| kv.set("attempt", 2)).unwrap();
+    assert!(with_kv(|_| with_kv(|_| Ok(()))).is_err());
+    end_hook(true).unwrap();
+
+    // A fresh connection sees the committed value
+    set_kv(Some(Storage::new(Some(path.clone())).unwrap()));
+    let value: Option<u32> = with_kv(|kv| kv.get("attempt")).unwrap();
+    assert_eq!(value, Some(2));
+    set_kv(None);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn transactions_nest_inside_dispatched_hooks() {
+    let path = PathBuf::from("/tmp/unitdata_nested.db");
+    let _ = fs::remove_file(&path);
+    set_kv(Some(Storage::new(Some(path.clone())).unwrap()));
+    let _harness = Harness::new("web/0");
+
+    let mut registry = HookRegistry::new().on("config-changed", |_| {
+        with_kv(|kv| kv.set("before", true))?;
+        with_kv(|kv| kv.transaction(|kv| kv.set("committed", true)))?;
+        let failed: Result<(), JujuError> = with_kv(|kv| {
+            kv.transaction(|kv| {
+                kv.set("rolled_back", true)?;
+                Err(JujuError::HookFailed("validation failed".to_string()))
+            })
+        });
+        assert!(failed.is_err());
+        with_kv(|kv| kv.set("after", true))
+    });
+    let context = HookContext::from_vars(vec![("JUJU_HOOK_NAME", "config-changed")]).unwrap();
+    registry.dispatch(&context).unwrap();
+
+    // Only the failed transaction's own write was rolled back
+    set_kv(Some(Storage::new(Some(path.clone())).unwrap()));
+    for &(key, expected) in &[("before", true), ("committed", true), ("rolled_back", false),
+                              ("after", true)] {
+        let value: Option<bool> = with_kv(|kv| kv.get(key)).unwrap();
+        assert_eq!(value.is_some(), expected, "{}", key);
+    }
+    set_kv(None);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn delta_and_snapshots() {
+    let path = PathBuf::from("/tmp/unitdata_delta.db");
+    let _ = fs::remove_file(&path);
+    let unitdata = Storage::new(Some(path.clone())).expect("Failed to connect to database");
+
+    let mut mapping: HashMap<String, u32> = HashMap::new();
+    mapping.insert("a".to_string(), 1);
+    mapping.insert("b".to_string(), 2);
+    let deltas = unitdata.snapshot(mapping.clone(), "counts.").unwrap();
+    assert_eq!(deltas.len(), 2);
+    assert_eq!(deltas["a"],
+               Delta {
+                   previous: None,
+                   current: Some(Value::from(1)),
+               });
+
+    mapping.insert("a".to_string(), 10);
+    mapping.remove("b");
+    let deltas = unitdata.delta(&mapping, "counts.").unwrap();
+    assert_eq!(deltas["a"],
+               Delta {
+                   previous: Some(Value::from(1)),
+                   current: Some(Value::from(10)),
+               });
+    assert_eq!(deltas["b"],
+               Delta {
+                   previous: Some(Value::from(2)),
+                   current: None,
+               });
+    unitdata.snapshot(mapping.clone(), "counts.").unwrap();
+    assert!(unitdata.delta(&mapping, "counts.").unwrap().is_empty());
+    let stored: Option<u32> = unitdata.get("counts.b").unwrap();
+    assert_eq!(stored, None);
+
+    let harness = Harness::new("web/0");
+    harness.set_config("port", 80);
+    assert_eq!(unitdata.snapshot_config().unwrap().len(), 1);
+    assert!(unitdata.snapshot_config().unwrap().is_empty());
+    harness.set_config("port", 8080);
+    assert_eq!(unitdata.snapshot_config().unwrap()["port"].previous,
+               Some(Value::from("80")));
+
+    let mut data = HashMap::new();
+    data.insert("host".to_string(), "10.0.0.2".to_string());
+    let (id, unit) = ("db:0".parse().unwrap(), "mysql/0".parse().unwrap());
+    assert_eq!(unitdata.snapshot_relation(&id, &unit, data).unwrap().len(), 1);
+    let stored: Option<String> = unitdata.get("rels.db:0.mysql/0.host").unwrap();
+    assert_eq!(stored, Some("10.0.0.2".to_string()));
+    fs::remove_file(&path).unwrap();
 }